@@ -0,0 +1,486 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Error as IOError;
+use std::path::PathBuf;
+
+use git2::Repository;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::Result;
+
+pub const CONFIG_FILE: &str = ".qit.toml";
+
+// Emojis inspired by https://gitmoji.dev/
+const DEFAULT_EMOJIS: &[(&str, &str)] = &[
+    ("chore", "🔨"),
+    ("feature", "✨"),
+    ("refactor", "♻️"),
+    ("fix", "🐛"),
+    ("test", "✅"),
+    ("style", "🎨"),
+    ("doc", "📝"),
+    ("deps", "📦"),
+    ("deploy", "🚀"),
+    ("wip", "🚧"),
+];
+
+/// Short names accepted in place of the full commit type.
+const TYPE_ALIASES: &[(&str, &str)] = &[("feat", "feature"), ("ref", "refactor"), ("docs", "doc")];
+
+#[derive(Debug, Default, Deserialize)]
+struct ConfigFile {
+    default_type: Option<String>,
+    allowed_scopes: Option<Vec<String>>,
+    #[serde(default)]
+    emojis: HashMap<String, String>,
+    #[serde(default)]
+    format: FormatConfig,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+    #[serde(default)]
+    default_scopes: HashMap<String, String>,
+    #[serde(default)]
+    template: TemplateConfig,
+    #[serde(default)]
+    validation: ValidationConfig,
+    #[serde(default)]
+    commit: CommitConfig,
+    #[serde(default)]
+    message: MessageConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct MessageConfig {
+    prefix_from_branch: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CommitConfig {
+    #[serde(default)]
+    exclude: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ValidationConfig {
+    pattern: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TemplateConfig {
+    body: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FormatConfig {
+    #[serde(default)]
+    emoji_position: EmojiPosition,
+    #[serde(default)]
+    emoji_style: EmojiStyle,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmojiPosition {
+    #[default]
+    Prefix,
+    Suffix,
+}
+
+/// Whether commit types are marked with their emoji, or with a text tag like
+/// `[feat]` for terminals that can't show emojis.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmojiStyle {
+    #[default]
+    Emoji,
+    Text,
+}
+
+/// The effective configuration: the builtin defaults, overridden by whatever
+/// `.qit.toml` is found at the root of the current repository.
+#[derive(Debug)]
+pub struct Config {
+    /// The config file that was loaded, if there was one.
+    path: Option<PathBuf>,
+    default_type: Option<String>,
+    allowed_scopes: Option<Vec<String>>,
+    emojis: Vec<(String, String)>,
+    emoji_position: EmojiPosition,
+    emoji_style: EmojiStyle,
+    tags: HashMap<String, String>,
+    default_scopes: HashMap<String, String>,
+    template: Option<String>,
+    /// A regex that commit subjects must match.
+    subject_pattern: Option<Regex>,
+    /// Pathspecs that are never staged automatically.
+    commit_exclude: Vec<String>,
+    /// A regex for the part of the branch name, eg. a ticket ID, that goes
+    /// in front of every commit message.
+    branch_prefix: Option<Regex>,
+}
+
+impl Config {
+    pub fn load() -> Result<Config> {
+        let (path, file) = match config_path() {
+            Some(path) if path.is_file() => {
+                let contents = fs::read_to_string(&path)?;
+                let file = toml::from_str(&contents).map_err(|err| {
+                    IOError::other(format!("Unable to parse {}: {}", path.display(), err))
+                })?;
+                (Some(path), file)
+            }
+            _ => return Ok(Config::builtin()),
+        };
+        Config::from_file(path, file)
+    }
+
+    /// The builtin defaults, as if there was no config file.
+    pub fn builtin() -> Config {
+        Config::from_file(None, ConfigFile::default()).expect("the builtin config is valid")
+    }
+
+    /// A config read from `contents` rather than the repository's file.
+    #[cfg(test)]
+    pub fn parse(contents: &str) -> Result<Config> {
+        let file = toml::from_str(contents).map_err(|err| IOError::other(err.to_string()))?;
+        Config::from_file(None, file)
+    }
+
+    fn from_file(path: Option<PathBuf>, mut file: ConfigFile) -> Result<Config> {
+        let subject_pattern = file
+            .validation
+            .pattern
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|err| IOError::other(format!("Invalid [validation] pattern: {}", err)))?;
+        let branch_prefix = file
+            .message
+            .prefix_from_branch
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|err| {
+                IOError::other(format!("Invalid [message] prefix_from_branch: {}", err))
+            })?;
+
+        let mut emojis: Vec<(String, String)> = DEFAULT_EMOJIS
+            .iter()
+            .map(|(type_, emoji)| {
+                let emoji = file
+                    .emojis
+                    .remove(*type_)
+                    .unwrap_or_else(|| emoji.to_string());
+                (type_.to_string(), emoji)
+            })
+            .collect();
+
+        // Custom types go after the builtin ones, sorted so the help output
+        // is stable.
+        let mut custom: Vec<(String, String)> = file.emojis.into_iter().collect();
+        custom.sort();
+        emojis.extend(custom);
+
+        Ok(Config {
+            path,
+            default_type: file.default_type,
+            allowed_scopes: file.allowed_scopes,
+            emojis,
+            emoji_position: file.format.emoji_position,
+            emoji_style: file.format.emoji_style,
+            tags: file.tags,
+            default_scopes: file.default_scopes,
+            template: file.template.body,
+            subject_pattern,
+            commit_exclude: file.commit.exclude,
+            branch_prefix,
+        })
+    }
+
+    /// All known commit types, in display order.
+    pub fn types(&self) -> Vec<&str> {
+        self.emojis
+            .iter()
+            .map(|(type_, _)| type_.as_str())
+            .collect()
+    }
+
+    /// All known commit types and their aliases, for argument validation.
+    pub fn type_names(&self) -> Vec<&str> {
+        let mut names = self.types();
+        names.extend(self.aliases().map(|(alias, _)| alias));
+        names
+    }
+
+    /// The aliases that apply, skipping any that are shadowed by a type of
+    /// the same name.
+    pub fn aliases(&self) -> impl Iterator<Item = (&'static str, &'static str)> + '_ {
+        TYPE_ALIASES
+            .iter()
+            .copied()
+            .filter(|(alias, type_)| self.emoji(alias).is_none() && self.emoji(type_).is_some())
+    }
+
+    /// Whether `name` is a known commit type or alias.
+    pub fn is_type(&self, name: &str) -> bool {
+        self.type_names().contains(&name)
+    }
+
+    /// Resolves an alias like `feat` to its canonical type, `feature`.
+    pub fn canonical_type<'a>(&self, type_: &'a str) -> &'a str {
+        self.aliases()
+            .find(|(alias, _)| *alias == type_)
+            .map(|(_, canonical)| canonical)
+            .unwrap_or(type_)
+    }
+
+    /// The type to commit with when none is given.
+    pub fn default_type(&self) -> Option<&str> {
+        self.default_type.as_deref()
+    }
+
+    /// Whether `scope` may be used as the area of a commit. Any scope is
+    /// allowed unless `allowed_scopes` is set.
+    pub fn is_allowed_scope(&self, scope: &str) -> bool {
+        match &self.allowed_scopes {
+            Some(allowed) => allowed.iter().any(|allowed| allowed == scope),
+            None => true,
+        }
+    }
+
+    pub fn allowed_scopes(&self) -> Option<&[String]> {
+        self.allowed_scopes.as_deref()
+    }
+
+    pub fn emoji_position(&self) -> EmojiPosition {
+        self.emoji_position
+    }
+
+    pub fn emoji_style(&self) -> EmojiStyle {
+        self.emoji_style
+    }
+
+    /// The text tag used in place of the emoji with the text style. Defaults
+    /// to the shortest name for the type, eg. `[feat]`.
+    pub fn tag(&self, type_: &str) -> String {
+        if let Some(tag) = self.tags.get(type_) {
+            return tag.clone();
+        }
+        // The type itself wins ties, being listed first
+        let aliases = self
+            .aliases()
+            .filter(|(_, canonical)| *canonical == type_)
+            .map(|(alias, _)| alias);
+        let name = [type_]
+            .into_iter()
+            .chain(aliases)
+            .min_by_key(|name| name.chars().count())
+            .unwrap_or(type_);
+        format!("[{}]", name)
+    }
+
+    /// The scope to use for commits of this type when none is given.
+    pub fn default_scope(&self, type_: &str) -> Option<&str> {
+        self.default_scopes.get(type_).map(|scope| scope.as_str())
+    }
+
+    /// The commit body template, with `{type}`, `{scope}` and `{message}`
+    /// placeholders.
+    pub fn template(&self) -> Option<&str> {
+        self.template.as_deref()
+    }
+
+    pub fn subject_pattern(&self) -> Option<&Regex> {
+        self.subject_pattern.as_ref()
+    }
+
+    pub fn commit_exclude(&self) -> &[String] {
+        &self.commit_exclude
+    }
+
+    pub fn branch_prefix(&self) -> Option<&Regex> {
+        self.branch_prefix.as_ref()
+    }
+
+    pub fn emojis(&self) -> &[(String, String)] {
+        &self.emojis
+    }
+
+    pub fn emoji(&self, type_: &str) -> Option<&str> {
+        self.emojis
+            .iter()
+            .find(|(name, _)| name == type_)
+            .map(|(_, emoji)| emoji.as_str())
+    }
+
+    /// Renders the effective configuration as TOML.
+    pub fn to_toml(&self) -> String {
+        let mut out = match &self.path {
+            Some(path) => format!("# Defaults merged with {}\n", path.display()),
+            None => "# Defaults, no config file found\n".to_string(),
+        };
+
+        if let Some(default_type) = &self.default_type {
+            out.push_str(&format!("default_type = {}\n", string(default_type)));
+        }
+        if let Some(allowed) = &self.allowed_scopes {
+            let allowed: Vec<String> = allowed.iter().map(|scope| string(scope)).collect();
+            out.push_str(&format!("allowed_scopes = [{}]\n", allowed.join(", ")));
+        }
+
+        out.push_str("\n[emojis]\n");
+        for (type_, emoji) in &self.emojis {
+            out.push_str(&format!("{} = {}\n", key(type_), string(emoji)));
+        }
+
+        out.push_str("\n[format]\n");
+        let position = match self.emoji_position {
+            EmojiPosition::Prefix => "prefix",
+            EmojiPosition::Suffix => "suffix",
+        };
+        out.push_str(&format!("emoji_position = {}\n", string(position)));
+        let style = match self.emoji_style {
+            EmojiStyle::Emoji => "emoji",
+            EmojiStyle::Text => "text",
+        };
+        out.push_str(&format!("emoji_style = {}\n", string(style)));
+
+        out.push_str("\n[tags]\n");
+        for type_ in self.types() {
+            out.push_str(&format!("{} = {}\n", key(type_), string(&self.tag(type_))));
+        }
+
+        out.push_str("\n[default_scopes]\n");
+        let mut scopes: Vec<(&String, &String)> = self.default_scopes.iter().collect();
+        scopes.sort();
+        for (type_, scope) in scopes {
+            out.push_str(&format!("{} = {}\n", key(type_), string(scope)));
+        }
+
+        out.push_str("\n[template]\n");
+        if let Some(body) = &self.template {
+            out.push_str(&format!("body = {}\n", string(body)));
+        }
+
+        out.push_str("\n[validation]\n");
+        if let Some(pattern) = &self.subject_pattern {
+            out.push_str(&format!("pattern = {}\n", string(pattern.as_str())));
+        }
+
+        out.push_str("\n[commit]\n");
+        let exclude: Vec<String> = self.commit_exclude.iter().map(|p| string(p)).collect();
+        out.push_str(&format!("exclude = [{}]\n", exclude.join(", ")));
+
+        out.push_str("\n[message]\n");
+        if let Some(pattern) = &self.branch_prefix {
+            out.push_str(&format!(
+                "prefix_from_branch = {}\n",
+                string(pattern.as_str())
+            ));
+        }
+        out
+    }
+}
+
+/// Writes a commented `.qit.toml` with the builtin defaults to the root of
+/// the current repository, returning its path.
+pub fn init(force: bool) -> Result<PathBuf> {
+    let path = config_path().ok_or_else(|| IOError::other("Not inside a git repository"))?;
+    if path.exists() && !force {
+        return Err(IOError::other(format!(
+            "{} already exists, use --force to overwrite it",
+            path.display()
+        ))
+        .into());
+    }
+    fs::write(&path, default_file())?;
+    Ok(path)
+}
+
+fn default_file() -> String {
+    let mut out = String::from(
+        "# qit configuration\n\n# The type to commit with when none is given\n# default_type = \"feature\"\n\n# The only areas commits may use. Any area is allowed if this isn't set\n# allowed_scopes = [\"cli\", \"api\"]\n\n# The emoji for each commit type. Adding an entry defines a new type.\n[emojis]\n",
+    );
+    for (type_, emoji) in DEFAULT_EMOJIS {
+        out.push_str(&format!("{} = {}\n", key(type_), string(emoji)));
+    }
+    out.push_str(
+        r#"
+[format]
+# Where the emoji goes in the subject, either "prefix" or "suffix"
+# emoji_position = "prefix"
+# "text" swaps the emoji for a tag like [feat], for terminals without emojis
+# emoji_style = "emoji"
+
+# The tags used with the text style, they default to eg. [feat] and [fix]
+[tags]
+# feature = "[feature]"
+
+# The area to use for a commit type when --area isn't given
+[default_scopes]
+# deploy = "prod"
+
+# A body added to every commit. {type}, {scope} and {message} are filled in
+[template]
+# body = "Testing:\n"
+
+# A regex that every commit subject must match
+[validation]
+# pattern = "^\\S+ \\w+(\\(\\w+\\))?: [A-Z]"
+
+# Paths that commit never stages, even when they aren't ignored
+[commit]
+# exclude = ["*.log", "tmp/"]
+
+# A regex for the part of the branch name to put in front of every message.
+# The first capture group is used if there is one, otherwise the whole match
+[message]
+# prefix_from_branch = "([A-Z]+-\\d+)"
+"#,
+    );
+    out
+}
+
+/// A TOML key, quoted if it can't be written bare.
+fn key(key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if bare {
+        key.to_string()
+    } else {
+        string(key)
+    }
+}
+
+/// A quoted and escaped TOML string.
+fn string(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+fn config_path() -> Option<PathBuf> {
+    let repo = Repository::discover(".").ok()?;
+    repo.workdir().map(|dir| dir.join(CONFIG_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_file_examples_parse() {
+        // Every commented out example should work once uncommented
+        let uncommented: String = default_file()
+            .lines()
+            .map(|line| match line.strip_prefix("# ") {
+                Some(example) if example.contains(" = ") => example,
+                _ => line,
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let config = Config::parse(&uncommented).unwrap();
+        assert_eq!(config.branch_prefix().unwrap().as_str(), "([A-Z]+-\\d+)");
+    }
+}
@@ -1,51 +1,441 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::env;
 use std::error::Error;
-use std::io::{Error as IOError, ErrorKind};
-use std::process::{Command, Stdio};
+use std::fmt;
+use std::fs;
+use std::io::{Error as IOError, IsTerminal, Read, Write};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgGroup, ArgMatches};
+use clap_complete::Shell;
 use git2::Repository;
+use serde::Serialize;
+
+use crate::config::{Config, EmojiPosition, EmojiStyle};
+
+mod config;
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
+/// Set by `--dry-run`: print git commands instead of running them.
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Set by `--no-pager`: never page the output of log, diff and blame.
+static NO_PAGER: AtomicBool = AtomicBool::new(false);
+
+/// Set by `--quiet`: discard git's output for `commit`, `push` and `undo`.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
 fn main() -> Result<()> {
-    let app = App::new("qit")
+    // A broken `.qit.toml` shouldn't stop `--help`, or `config init --force`
+    // from replacing it, so only fail once the command turns out to need it
+    let (config, config_error) = match Config::load() {
+        Ok(config) => (config, None),
+        Err(err) => (Config::builtin(), Some(err)),
+    };
+    let commit_help = commit_help(&config);
+    let mut app = app(&config, &commit_help);
+
+    let matches = app.get_matches_mut();
+    DRY_RUN.store(matches.is_present("dry-run"), Ordering::Relaxed);
+    QUIET.store(matches.is_present("quiet"), Ordering::Relaxed);
+    NO_PAGER.store(matches.is_present("no-pager"), Ordering::Relaxed);
+    if let Some(err) = config_error {
+        if uses_config(&matches) {
+            handle::<()>(Err(err));
+        } else if !is_quiet() {
+            eprintln!("⚠️  Using the default config. {}", err);
+        }
+    }
+
+    match matches.subcommand() {
+        Some(("init", args)) => {
+            let branch = args.value_of("branch");
+            handle(init(&config, branch, args.is_present("initial-commit")));
+        }
+        Some(("commit", args)) if args.is_present("fixup") || args.is_present("squash") => {
+            let fixup = match args.value_of("fixup") {
+                Some(target) => format!("--fixup={}", target),
+                None => format!("--squash={}", args.value_of("squash").unwrap()),
+            };
+            handle(fixup_commit(
+                &config,
+                &fixup,
+                &commit_options(&config, args),
+            ));
+        }
+        Some(("commit", args)) => {
+            // With only one positional given, it's the message if it isn't
+            // a type, eg. `qit commit "Add thing"`.
+            // `--paths` takes everything after it, so the positionals may be
+            // at the end of it
+            let (_, rest) = handle(commit_paths(args));
+            let positionals: Vec<&str> = args
+                .value_of("type")
+                .into_iter()
+                .chain(args.value_of("message"))
+                .chain(rest)
+                .collect();
+            if positionals.len() > 2 {
+                handle::<()>(Err(IOError::other(format!(
+                    "Expected a type and a message, got {}",
+                    positionals.join(" ")
+                ))
+                .into()));
+            }
+            let mut positionals = positionals.into_iter();
+            let (type_, message) = match (positionals.next(), positionals.next()) {
+                (Some(type_), message) if config.is_type(type_) => (Some(type_), message),
+                (Some(message), None) => (None, Some(message)),
+                (Some(type_), Some(_)) => handle(Err(unknown_type(type_))),
+                (None, message) => (None, message),
+            };
+            if message.is_some() && args.is_present("message-file") {
+                handle::<()>(Err(IOError::other(
+                    "A message can't be given along with --message-file",
+                )
+                .into()));
+            }
+            let type_ = handle(resolve_type(&config, type_));
+            let message = match (message, args.value_of("message-file")) {
+                (None, Some(path)) => handle(fs::read_to_string(path).map_err(|err| {
+                    IOError::other(format!("Unable to read {}: {}", path, err)).into()
+                })),
+                (Some(message), _) => message.to_string(),
+                (None, None) if args.is_present("stdin") => handle(read_stdin()),
+                (None, None) => handle(edit_message()),
+            };
+            let options = commit_options(&config, args);
+            handle(commit(&config, &type_, &message, &options));
+        }
+        Some(("wip", args)) => {
+            let message = args.value_of("message").unwrap();
+            handle(commit(
+                &config,
+                "wip",
+                message,
+                &CommitOptions::new(&config),
+            ));
+        }
+        Some(("amend", args)) => {
+            let type_ = args
+                .value_of("type")
+                .map(|type_| config.canonical_type(type_));
+            let message = args.value_of("message").or(args.value_of("reword"));
+            let options = AmendOptions {
+                area: args.value_of("area"),
+                reword: args.is_present("reword"),
+                no_edit: args.is_present("no-edit"),
+                no_add: args.is_present("no-add"),
+            };
+            handle(amend(&config, type_, message, &options));
+        }
+        Some(("log", args)) => {
+            let max_count = args
+                .value_of("max-count")
+                .map(|n| handle(parse_positive("max-count", n)));
+            let options = LogOptions {
+                short: args.is_present("short"),
+                max_count,
+                since: args.value_of("since"),
+                until: args.value_of("until"),
+                all: args.is_present("all"),
+                graph: args.is_present("graph"),
+            };
+            if args.is_present("json") {
+                handle(log_json(max_count));
+            } else if args.is_present("count") {
+                handle(log_count(&options));
+            } else {
+                handle(log(&options));
+            }
+        }
+        Some(("graph", args)) => {
+            let options = LogOptions {
+                short: true,
+                max_count: args
+                    .value_of("max-count")
+                    .map(|n| handle(parse_positive("max-count", n))),
+                all: true,
+                graph: true,
+                ..Default::default()
+            };
+            handle(log(&options));
+        }
+        Some(("last", args)) => handle(last(args.is_present("oneline"))),
+        Some(("push", args)) => {
+            let force = if args.is_present("hard-force") {
+                Force::Hard
+            } else if args.is_present("force") || args.is_present("force-with-lease") {
+                Force::Lease
+            } else {
+                Force::None
+            };
+            let remote = args.value_of("remote");
+            let options = PushOptions {
+                force,
+                remote,
+                set_upstream: !args.is_present("no-upstream"),
+                dry_run: args.is_present("dry-run"),
+                check: !args.is_present("no-check"),
+            };
+            handle(push(&options));
+        }
+        Some(("pull", args)) => {
+            let merge = args.is_present("merge");
+            let autostash = args.is_present("autostash");
+            handle(pull(merge, autostash));
+        }
+        Some(("fetch", args)) => {
+            let all = args.is_present("all");
+            let prune = args.is_present("prune");
+            handle(fetch(all, prune));
+        }
+        Some(("sync", args)) => {
+            let force = if args.is_present("force-with-lease") {
+                Force::Lease
+            } else {
+                Force::None
+            };
+            handle(sync(force));
+        }
+        Some(("undo", args)) => {
+            let count = handle(parse_positive("count", args.value_of("count").unwrap()));
+            handle(undo(count, args.is_present("force")));
+        }
+        Some(("squash", args)) => {
+            let count = handle(parse_positive("count", args.value_of("count").unwrap()));
+            let type_ = args.value_of("type");
+            handle(squash(&config, count, type_, args.value_of("message")));
+        }
+        Some(("redo", _)) => handle(redo()),
+        Some(("reflog", args)) => {
+            let max_count = handle(parse_positive(
+                "max-count",
+                args.value_of("max-count").unwrap(),
+            ));
+            handle(reflog(args.value_of("branch"), max_count));
+        }
+        Some(("rebase", args)) => {
+            let in_progress = ["continue", "abort", "skip"]
+                .into_iter()
+                .find(|action| args.is_present(action));
+            if let Some(action) = in_progress {
+                handle(run_git_checked(&["rebase", &format!("--{}", action)]));
+            } else {
+                let options = RebaseOptions {
+                    upstream: args.value_of("upstream"),
+                    count: args
+                        .value_of("count")
+                        .map(|n| handle(parse_positive("interactive", n))),
+                    onto: args.value_of("onto"),
+                    autostash: args.is_present("autostash"),
+                };
+                handle(rebase(&options));
+            }
+        }
+        Some(("switch", args)) => handle(switch_branch(args.value_of("branch").unwrap())),
+        Some(("restore", args)) => {
+            let paths: Vec<&str> = args.values_of("paths").unwrap().collect();
+            let staged = args.is_present("staged");
+            handle(restore(&paths, staged, args.is_present("force")));
+        }
+        Some(("diff", args)) => {
+            let staged = args.is_present("staged");
+            handle(diff(staged, args.value_of("path")));
+        }
+        Some(("blame", args)) => {
+            let file = args.value_of("file").unwrap();
+            handle(blame(file, args.value_of("lines")));
+        }
+        Some(("branch", args)) => match args.value_of("delete") {
+            Some(branch) => handle(delete_branch(branch)),
+            None => handle(list_branches(&BranchListOptions {
+                remote: args.is_present("remote"),
+                porcelain: args.is_present("porcelain"),
+                current_marker: args.is_present("show-current-marker"),
+            })),
+        },
+        Some(("recent", args)) => {
+            let count = handle(parse_positive("count", args.value_of("count").unwrap()));
+            handle(recent_branches(count));
+        }
+        Some(("prune-merged", args)) => {
+            let into = args.value_of("into").unwrap();
+            handle(prune_merged(into, args.is_present("force")));
+        }
+        Some(("worktree", args)) => match args.subcommand() {
+            Some(("add", args)) => {
+                let path = args.value_of("path").unwrap();
+                handle(add_worktree(path, args.value_of("branch").unwrap()));
+            }
+            Some(("remove", args)) => handle(run_git_checked(&[
+                "worktree",
+                "remove",
+                args.value_of("path").unwrap(),
+            ])),
+            _ => handle(list_worktrees()),
+        },
+        Some(("bisect", args)) => match args.subcommand() {
+            Some((action @ ("good" | "bad"), args)) => {
+                handle(bisect(action, args.value_of("rev")));
+            }
+            Some((action, _)) => handle(bisect(action, None)),
+            None => unreachable!("bisect requires a subcommand"),
+        },
+        Some(("stash", args)) => {
+            let action = args.subcommand_name().unwrap_or("push");
+            handle(stash(action, args.value_of("message")));
+        }
+        Some(("clean", args)) => {
+            handle(clean(args.is_present("force"), args.is_present("ignored")));
+        }
+        Some(("rename", args)) => {
+            let name = args.value_of("name").unwrap();
+            handle(rename_branch(name, args.is_present("remote")));
+        }
+        Some(("cherry-pick", args)) => {
+            if args.is_present("abort") {
+                handle(run_git_checked(&["cherry-pick", "--abort"]));
+            } else if args.is_present("continue") {
+                handle(run_git_checked(&["cherry-pick", "--continue"]));
+            } else {
+                let refs: Vec<&str> = args.values_of("refs").unwrap().collect();
+                handle(cherry_pick(&refs, args.is_present("force")));
+            }
+        }
+        Some(("tag", args)) => {
+            let name = args.value_of("name").unwrap();
+            let message = args.value_of("message").unwrap_or(name);
+            handle(tag(&config, name, message, args.is_present("push")));
+        }
+        Some(("status", args)) => handle(status(args.is_present("stat"))),
+        Some(("conflicts", args)) => handle(conflicts(args.is_present("stat"))),
+        Some(("remote", args)) => match args.subcommand() {
+            Some(("add", args)) => handle(run_git_checked(&[
+                "remote",
+                "add",
+                args.value_of("name").unwrap(),
+                args.value_of("url").unwrap(),
+            ])),
+            Some(("remove", args)) => handle(run_git_checked(&[
+                "remote",
+                "remove",
+                args.value_of("name").unwrap(),
+            ])),
+            _ => handle(list_remotes()),
+        },
+        Some(("whoami", _)) => handle(whoami()),
+        Some(("config", args)) => match args.subcommand() {
+            Some(("init", args)) => {
+                let path = handle(config::init(args.is_present("force")));
+                println!("Wrote {}", path.display());
+            }
+            _ => print!("{}", config.to_toml()),
+        },
+        Some(("completions", args)) => {
+            let shell = args.value_of("shell").unwrap();
+            handle(completions(&mut app, shell));
+        }
+        Some(("emoji", args)) => {
+            let type_ = config.canonical_type(args.value_of("type").unwrap());
+            let emoji = handle(emoji(&config, type_));
+            if !emoji.is_empty() {
+                println!("{}", emoji);
+            }
+        }
+        _ => {
+            let counts = handle(repo_status());
+            if matches.is_present("json") {
+                println!(
+                    "{}",
+                    handle(serde_json::to_string(&counts).map_err(Into::into))
+                );
+            } else if matches.is_present("porcelain") {
+                println!("{}", counts.total);
+            } else if counts.total == 0 {
+                println!("✨ {}", paint(GREEN, "working tree clean"));
+            } else {
+                let summary = format!(
+                    "{} staged, {} unstaged, {} untracked",
+                    counts.staged, counts.unstaged, counts.untracked
+                );
+                println!("📝 {}", paint(YELLOW, &summary));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn app<'a>(config: &'a Config, commit_help: &'a str) -> App<'a> {
+    App::new("qit")
+        .arg(
+            Arg::new("dry-run")
+                .help("Print the git commands that would be run, without running them")
+                .long("dry-run")
+                .short('n')
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("quiet")
+                .help("Hide git's output for commit, push and undo. Failures are still reported")
+                .long("quiet")
+                .short('q')
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("no-pager")
+                .help("Print the output of log, diff and blame directly, without a pager")
+                .long("no-pager")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("porcelain")
+                .help("When run without a subcommand, only print the number of changed files")
+                .long("porcelain")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("json")
+                .help("When run without a subcommand, print the number of changed files and the branch as JSON")
+                .long("json")
+                .takes_value(false)
+                .conflicts_with("porcelain"),
+        )
+        // Init
+        .subcommand(
+            App::new("init")
+                .about("Creates a new repository in the current directory")
+                .arg(
+                    Arg::new("initial-commit")
+                        .help("Also make an empty `chore: Initial commit` commit")
+                        .long("initial-commit")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("branch")
+                        .help("The name of the initial branch")
+                        .long("branch")
+                        .short('b')
+                        .takes_value(true),
+                ),
+        )
         // Commit
         .subcommand(
             App::new("commit")
                 .alias("c")
                 .about("Commits with a meaningful commit message")
-                .after_help("
-Format:
-    <emoji> <type>[(<area>)]: <message>
-
-Emojis:
-       chore 🔨
-     feature ✨
-    refactor ♻️
-         fix 🐛
-        test ✅
-       style 🎨
-         doc 📝
-        deps 📦
-      deploy 🚀
-         wip 🚧
-    
-    Emojis inspired by https://gitmoji.dev/
-
-Examples:
-    ✨ feature: Add thing
-    ✨ feature(cli): Improve args
-    🔨 chore: Do thing
-    🚀 deploy(api): Deploy to production
-                ")
+                .after_help(commit_help)
                 .arg(
                     Arg::new("type")
-                        .help("The type of commit")
-                        .possible_values(vec![
-                            "chore", "feature", "refactor", "fix", "test", "style", "doc", "deps", "deploy", "wip",
-                        ])
-                        .required(true),
+                        .help("The type of commit, see below. If omitted, QIT_DEFAULT_TYPE or default_type is used, otherwise you'll be asked to pick one")
+                        .required(false),
                 )
                 .arg(
                     Arg::new("area")
@@ -55,10 +445,86 @@ Examples:
                         .takes_value(true)
                         .required(false),
                 )
+                .arg(
+                    Arg::new("conventional")
+                        .help("Format the subject as plain Conventional Commits, without emoji. Can also be enabled with QIT_FORMAT=conventional")
+                        .long("conventional")
+                        .takes_value(false)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("no-emoji")
+                        .help("Leave the emoji out of the subject, like QIT_DISABLE_EMOJIS")
+                        .long("no-emoji")
+                        .takes_value(false)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("emoji-suffix")
+                        .help("Put the emoji at the end of the subject instead of the start")
+                        .long("emoji-suffix")
+                        .takes_value(false)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("scope-from-branch")
+                        .help("Use the last `/`-separated segment of the branch name as the area, if --area isn't given")
+                        .long("scope-from-branch")
+                        .takes_value(false)
+                        .required(false),
+                )
                 .arg(
                     Arg::new("message")
-                        .help("The commit message")
-                        .required(true),
+                        .help("The commit message. If omitted, $EDITOR is opened to write it")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("stdin")
+                        .help("Read the commit message from stdin")
+                        .long("stdin")
+                        .takes_value(false)
+                        .conflicts_with("message"),
+                )
+                .arg(
+                    Arg::new("fixup")
+                        .help("Make a fixup commit for this commit, to be squashed by `git rebase --autosquash`")
+                        .long("fixup")
+                        .takes_value(true)
+                        .value_name("commit")
+                        .conflicts_with_all(&["type", "message", "stdin", "message-file", "squash"]),
+                )
+                .arg(
+                    Arg::new("squash")
+                        .help("Like --fixup, but the commit's message is kept when squashing")
+                        .long("squash")
+                        .takes_value(true)
+                        .value_name("commit")
+                        .conflicts_with_all(&["type", "message", "stdin", "message-file"]),
+                )
+                .arg(
+                    Arg::new("message-file")
+                        .help("Read the commit message from a file. The first line is the subject, the rest is the body")
+                        .long("message-file")
+                        .short('F')
+                        .takes_value(true)
+                        .value_name("path")
+                        .conflicts_with_all(&["message", "stdin"]),
+                )
+                .arg(
+                    Arg::new("body")
+                        .help("A paragraph of the commit body. May be repeated for multiple paragraphs")
+                        .long("body")
+                        .short('b')
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("amend")
+                        .help("Amend HEAD with the formatted message instead of making a new commit")
+                        .long("amend")
+                        .takes_value(false)
+                        .conflicts_with_all(&["fixup", "squash"]),
                 )
                 .arg(
                     Arg::new("no-verify")
@@ -67,6 +533,183 @@ Examples:
                         .short('n')
                         .takes_value(false)
                         .required(false),
+                )
+                .arg(
+                    Arg::new("breaking")
+                        .help("Marks the commit as a breaking change, adding a `!` after the type")
+                        .long("breaking")
+                        .short('B')
+                        .takes_value(false)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("breaking-desc")
+                        .help("The description for the `BREAKING CHANGE:` footer")
+                        .long("breaking-desc")
+                        .takes_value(true)
+                        .requires("breaking")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("author")
+                        .help("Override the commit author. Takes `Name <email>`")
+                        .long("author")
+                        .takes_value(true)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("co-author")
+                        .help("Adds a `Co-authored-by:` trailer. Takes `Name <email>`, and may be repeated")
+                        .long("co-author")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("trailer")
+                        .help("Adds a trailer, eg. `Refs: #123`. Takes `Key: value`, and may be repeated")
+                        .long("trailer")
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::new("tracked-only")
+                        .help("Only stage changes to files that are already tracked (`git add -u`)")
+                        .long("tracked-only")
+                        .short('t')
+                        .takes_value(false)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("no-add")
+                        .help("Don't stage anything, only commit changes that are already staged")
+                        .long("no-add")
+                        .takes_value(false)
+                        .conflicts_with("tracked-only")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("paths")
+                        .help("Only stage these paths. The type and message can follow them, eg. `--paths a.rs b.rs \"Add thing\"`")
+                        .long("paths")
+                        .short('p')
+                        .takes_value(true)
+                        .multiple_values(true)
+                        .multiple_occurrences(true)
+                        .conflicts_with_all(&["tracked-only", "no-add"]),
+                )
+                .arg(
+                    Arg::new("max-subject")
+                        .help("Warn when the subject is longer than this many characters [default: 72]")
+                        .long("max-subject")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("count-emoji")
+                        .help("Include the emoji when measuring the subject length")
+                        .long("count-emoji")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("strict")
+                        .help("Refuse to commit when the subject is too long, rather than warning")
+                        .long("strict")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("print-only")
+                        .help("Print the formatted subject instead of committing")
+                        .long("print-only")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("show-staged")
+                        .help("List the files that would be committed, and ask before committing them")
+                        .long("show-staged")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("sign")
+                        .help("GPG-sign the commit. Also enabled by QIT_SIGN=true, or git's commit.gpgsign")
+                        .long("sign")
+                        .short('S')
+                        .takes_value(false)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("no-sign")
+                        .help("Don't sign the commit, even if QIT_SIGN or commit.gpgsign is set")
+                        .long("no-sign")
+                        .takes_value(false)
+                        .conflicts_with("sign"),
+                )
+                .arg(
+                    Arg::new("signoff")
+                        .help("Add a Signed-off-by trailer. Can also be enabled with QIT_SIGNOFF=true")
+                        .long("signoff")
+                        .short('s')
+                        .takes_value(false),
+                ),
+        )
+        // WIP
+        .subcommand(
+            App::new("wip")
+                .about("Commits everything as a work-in-progress checkpoint")
+                .arg(
+                    Arg::new("message")
+                        .help("The commit message")
+                        .default_value("WIP"),
+                ),
+        )
+        // Amend
+        .subcommand(
+            App::new("amend")
+                .alias("am")
+                .about("Amends the last commit, optionally rewriting its message")
+                .after_help("If no type / message is given, the editor is opened to amend the message.")
+                .arg(
+                    Arg::new("type")
+                        .help("The type of commit")
+                        .possible_values(config.type_names())
+                        .requires("new-message"),
+                )
+                .arg(
+                    Arg::new("area")
+                        .help("The section of the code this commit focuses on")
+                        .long("area")
+                        .short('a')
+                        .takes_value(true)
+                        .requires("type"),
+                )
+                .arg(
+                    Arg::new("message")
+                        .help("The new commit message")
+                        .requires("type"),
+                )
+                .arg(
+                    Arg::new("reword")
+                        .help("Only reword the last commit, leaving out any staged changes")
+                        .long("reword")
+                        .short('r')
+                        .takes_value(true)
+                        .value_name("message")
+                        .requires("type")
+                        .conflicts_with_all(&["message", "no-edit"]),
+                )
+                .group(ArgGroup::new("new-message").args(&["message", "reword"]))
+                .arg(
+                    Arg::new("no-edit")
+                        .help("Stage all changes and fold them into the last commit, keeping its message")
+                        .long("no-edit")
+                        .takes_value(false)
+                        .conflicts_with("type"),
+                )
+                .arg(
+                    Arg::new("no-add")
+                        .help("Only fold in changes that are already staged, without staging anything")
+                        .long("no-add")
+                        .visible_alias("staged-only")
+                        .takes_value(false),
                 ),
         )
         // Push
@@ -76,200 +719,3073 @@ Examples:
                 .about("Pushes the current branch to the remote. Will not push if there are uncommitted changes.")
                 .arg(
                     Arg::new("force")
-                        .help("Force push. Ignores uncommitted changes. **WARNING**: This is the same as `git push -f`!")
+                        .help("Force push. Ignores uncommitted changes. This is the same as `git push --force-with-lease`")
                         .long("force")
                         .short('f')
                         .takes_value(false),
+                )
+                .arg(
+                    Arg::new("force-with-lease")
+                        .help("Alias for --force")
+                        .long("force-with-lease")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("remote")
+                        .help("The remote to push to, instead of the default")
+                        .long("remote")
+                        .short('r')
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("no-upstream")
+                        .help("Don't set the upstream when pushing a branch that doesn't have one")
+                        .long("no-upstream")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("no-check")
+                        .help("Push without first checking whether the branch is behind its upstream")
+                        .long("no-check")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .help("Ask the remote which refs would be updated, without pushing anything")
+                        .long("dry-run")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("hard-force")
+                        .help("Force push. Ignores uncommitted changes. **WARNING**: This is the same as `git push --force`!")
+                        .long("hard-force")
+                        .takes_value(false),
                 ),
         )
-        // Undo
-        .subcommand(
-            App::new("undo")
-                .alias("u")
-                .about("Undoes the last commit"),
-        )
-        // Log
+        // Pull
         .subcommand(
-            App::new("log").alias("l").about("Shows the git log").arg(
-                Arg::new("short")
-                    .long("short")
-                    .short('s')
-                    .help("Whether to show a shortened git log"),
-            ),
+            App::new("pull")
+                .alias("pl")
+                .about("Pulls the current branch from the remote, rebasing by default. Will not pull if there are uncommitted changes.")
+                .arg(
+                    Arg::new("merge")
+                        .help("Merge instead of rebasing")
+                        .long("merge")
+                        .short('m')
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("autostash")
+                        .help("Stash uncommitted changes before pulling and reapply them afterwards")
+                        .long("autostash")
+                        .takes_value(false),
+                ),
         )
-        // Branch
+        // Fetch
         .subcommand(
-            App::new("switch")
-                .alias("s")
-                .about("Switch branches, creating as needed")
+            App::new("fetch")
+                .alias("f")
+                .about("Fetches from the remote without changing any local branches")
                 .arg(
-                    Arg::new("branch")
-                        .help("The branch to switch to")
-                        .required(true),
+                    Arg::new("all")
+                        .help("Fetch all remotes")
+                        .long("all")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("prune")
+                        .help("Remove remote-tracking branches that no longer exist on the remote")
+                        .long("prune")
+                        .short('p')
+                        .takes_value(false),
                 ),
         )
-        ;
+        // Sync
+        .subcommand(
+            App::new("sync")
+                .about("Pulls the current branch with a rebase, then pushes it. Will not run if there are uncommitted changes.")
+                .arg(
+                    Arg::new("force-with-lease")
+                        .help("Push with `git push --force-with-lease`")
+                        .long("force-with-lease")
+                        .takes_value(false),
+                ),
+        )
+        // Undo
+        .subcommand(
+            App::new("undo")
+                .alias("u")
+                .about("Undoes the last commit(s)")
+                .arg(
+                    Arg::new("count")
+                        .help("The number of commits to undo")
+                        .default_value("1"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .help("Undo without asking, even if the commit has already been pushed")
+                        .long("force")
+                        .short('f')
+                        .takes_value(false),
+                ),
+        )
+        // Squash
+        .subcommand(
+            App::new("squash")
+                .about("Combines the last N commits into one. Will not squash if there are uncommitted changes.")
+                .after_help("If no message is given, $EDITOR is opened to write it.")
+                .arg(
+                    Arg::new("count")
+                        .help("The number of commits to squash")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("type")
+                        .help("The type of the combined commit. If omitted, the default type is used or you'll be asked")
+                        .possible_values(config.type_names()),
+                )
+                .arg(Arg::new("message").help("The message of the combined commit")),
+        )
+        // Redo
+        .subcommand(
+            App::new("redo")
+                .about("Recommits the changes removed by the last undo, with the same message"),
+        )
+        // Reflog
+        .subcommand(
+            App::new("reflog")
+                .about("Shows where HEAD has been, to find commits lost to undo or reset")
+                .arg(
+                    Arg::new("branch")
+                        .help("Show where this branch has been instead")
+                        .long("branch")
+                        .short('b')
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("max-count")
+                        .help("Only show this many entries")
+                        .long("max-count")
+                        .short('n')
+                        .takes_value(true)
+                        .default_value("20"),
+                ),
+        )
+        // Rebase
+        .subcommand(
+            App::new("rebase")
+                .about("Interactively rebases the current branch. Will not start if there are uncommitted changes.")
+                .arg(
+                    Arg::new("upstream")
+                        .help("The ref to rebase onto, defaults to the branch's upstream")
+                        .conflicts_with("count"),
+                )
+                .arg(
+                    Arg::new("count")
+                        .help("Rebase the last N commits, ie. onto `HEAD~N`")
+                        .long("interactive")
+                        .short('i')
+                        .takes_value(true)
+                        .value_name("N"),
+                )
+                .arg(
+                    Arg::new("onto")
+                        .help("Replay the commits on top of this ref instead")
+                        .long("onto")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("autostash")
+                        .help("Stash uncommitted changes before rebasing and reapply them afterwards")
+                        .long("autostash")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("continue")
+                        .help("Continue the rebase in progress")
+                        .long("continue")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("abort")
+                        .help("Abort the rebase in progress")
+                        .long("abort")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("skip")
+                        .help("Skip the current commit of the rebase in progress")
+                        .long("skip")
+                        .takes_value(false),
+                )
+                .group(
+                    ArgGroup::new("in-progress")
+                        .args(&["continue", "abort", "skip"])
+                        .conflicts_with_all(&["upstream", "count", "onto", "autostash"]),
+                ),
+        )
+        // Log
+        .subcommand(
+            App::new("log")
+                .alias("l")
+                .about("Shows the git log")
+                .arg(
+                    Arg::new("short")
+                        .long("short")
+                        .short('s')
+                        .help("Whether to show a shortened git log"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Print the log as a JSON array")
+                        .conflicts_with_all(&["short", "since", "until", "all", "graph"]),
+                )
+                .arg(
+                    Arg::new("count")
+                        .long("count")
+                        .short('c')
+                        .help("Print the number of commits instead of the commits themselves")
+                        .conflicts_with_all(&["short", "json", "graph"]),
+                )
+                .arg(
+                    Arg::new("all")
+                        .long("all")
+                        .short('a')
+                        .help("Show commits from all branches, not just the current one"),
+                )
+                .arg(
+                    Arg::new("graph")
+                        .long("graph")
+                        .short('g')
+                        .help("Draw the branch graph alongside the log"),
+                )
+                .arg(
+                    Arg::new("max-count")
+                        .long("max-count")
+                        .short('n')
+                        .help("Only show this many commits")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .help("Only show commits more recent than this date, eg. `2 weeks ago`")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("until")
+                        .long("until")
+                        .help("Only show commits older than this date")
+                        .takes_value(true),
+                ),
+        )
+        // Graph
+        .subcommand(
+            App::new("graph")
+                .about("Shows a compact graph of commits on all branches")
+                .arg(
+                    Arg::new("max-count")
+                        .long("max-count")
+                        .short('n')
+                        .help("Only show this many commits")
+                        .takes_value(true),
+                ),
+        )
+        // Last
+        .subcommand(
+            App::new("last")
+                .about("Shows the most recent commit")
+                .arg(
+                    Arg::new("oneline")
+                        .help("Only show the commit's summary")
+                        .long("oneline")
+                        .takes_value(false),
+                ),
+        )
+        // Diff
+        .subcommand(
+            App::new("diff")
+                .alias("d")
+                .about("Shows unstaged changes")
+                .arg(
+                    Arg::new("staged")
+                        .help("Show staged changes instead")
+                        .long("staged")
+                        .short('s')
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("path")
+                        .help("Only show changes to this path")
+                        .required(false),
+                ),
+        )
+        // Blame
+        .subcommand(
+            App::new("blame")
+                .about("Shows who last changed each line of a file")
+                .arg(Arg::new("file").help("The file to blame").required(true))
+                .arg(
+                    Arg::new("lines")
+                        .help("Only blame this range of lines, eg. `10,20`")
+                        .short('L')
+                        .takes_value(true)
+                        .value_name("start,end"),
+                ),
+        )
+        // Branch
+        .subcommand(
+            App::new("switch")
+                .alias("s")
+                .about("Switch branches, creating as needed")
+                .arg(
+                    Arg::new("branch")
+                        .help("The branch to switch to, or `-` for the previous one")
+                        .required(true),
+                ),
+        )
+        // Restore
+        .subcommand(
+            App::new("restore")
+                .about("Discards unstaged changes to files. Asks for confirmation unless --force is passed.")
+                .arg(
+                    Arg::new("paths")
+                        .help("The files to restore")
+                        .multiple_values(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("staged")
+                        .help("Unstage the files instead, keeping the changes")
+                        .long("staged")
+                        .short('s')
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("force")
+                        .help("Discard the changes without asking")
+                        .long("force")
+                        .short('f')
+                        .takes_value(false),
+                ),
+        )
+        // Branch
+        .subcommand(
+            App::new("branch")
+                .alias("b")
+                .about("Lists branches, or deletes one")
+                .arg(
+                    Arg::new("delete")
+                        .help("The branch to delete")
+                        .long("delete")
+                        .short('d')
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("remote")
+                        .help("Include remote-tracking branches in the listing")
+                        .long("remote")
+                        .short('r')
+                        .takes_value(false)
+                        .conflicts_with("delete"),
+                )
+                .arg(
+                    Arg::new("porcelain")
+                        .help("Print one branch name per line, without markers or colour")
+                        .long("porcelain")
+                        .takes_value(false)
+                        .conflicts_with("delete"),
+                )
+                .arg(
+                    Arg::new("show-current-marker")
+                        .help("With --porcelain, prefix the current branch with `*`")
+                        .long("show-current-marker")
+                        .takes_value(false)
+                        .requires("porcelain"),
+                ),
+        )
+        // Recent
+        .subcommand(
+            App::new("recent")
+                .about("Lists recently checked-out branches, most recent first")
+                .arg(
+                    Arg::new("count")
+                        .help("The number of branches to list")
+                        .default_value("10"),
+                ),
+        )
+        // Prune merged
+        .subcommand(
+            App::new("prune-merged")
+                .about("Lists local branches that are fully merged. Pass --force to delete them.")
+                .arg(
+                    Arg::new("into")
+                        .help("The branch they must be merged into")
+                        .long("into")
+                        .takes_value(true)
+                        .default_value("main"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .help("Delete the merged branches instead of listing them")
+                        .long("force")
+                        .short('f')
+                        .takes_value(false),
+                ),
+        )
+        // Worktree
+        .subcommand(
+            App::new("worktree")
+                .about("Lists linked worktrees, marking the current one")
+                .subcommand(
+                    App::new("add")
+                        .about("Checks out a branch in a new worktree, creating the branch as needed")
+                        .arg(Arg::new("path").help("Where to create the worktree").required(true))
+                        .arg(Arg::new("branch").help("The branch to check out").required(true)),
+                )
+                .subcommand(App::new("list").about("Lists worktrees"))
+                .subcommand(
+                    App::new("remove")
+                        .about("Removes a worktree")
+                        .arg(Arg::new("path").help("The worktree to remove").required(true)),
+                ),
+        )
+        // Bisect
+        .subcommand(
+            App::new("bisect")
+                .about("Finds the commit that introduced a bug with git bisect")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .subcommand(App::new("start").about("Starts bisecting"))
+                .subcommand(
+                    App::new("good")
+                        .about("Marks a revision as good")
+                        .arg(Arg::new("rev").help("The revision to mark, defaults to HEAD")),
+                )
+                .subcommand(
+                    App::new("bad")
+                        .about("Marks a revision as bad")
+                        .arg(Arg::new("rev").help("The revision to mark, defaults to HEAD")),
+                )
+                .subcommand(
+                    App::new("reset").about("Stops bisecting and goes back to the original branch"),
+                ),
+        )
+        // Stash
+        .subcommand(
+            App::new("stash")
+                .about("Stashes uncommitted changes")
+                .arg(
+                    Arg::new("message")
+                        .help("A message describing the stash")
+                        .long("message")
+                        .short('m')
+                        .takes_value(true),
+                )
+                .subcommand(App::new("pop").about("Reapplies and drops the latest stash"))
+                .subcommand(App::new("list").about("Lists stashes")),
+        )
+        // Clean
+        .subcommand(
+            App::new("clean")
+                .about("Lists untracked files that would be removed. Pass --force to actually remove them.")
+                .arg(
+                    Arg::new("force")
+                        .help("Remove the untracked files instead of listing them")
+                        .long("force")
+                        .short('f')
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("ignored")
+                        .help("Also remove ignored files")
+                        .long("ignored")
+                        .short('x')
+                        .takes_value(false),
+                ),
+        )
+        // Rename
+        .subcommand(
+            App::new("rename")
+                .about("Renames the current branch")
+                .arg(
+                    Arg::new("name")
+                        .help("The new name of the branch")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("remote")
+                        .help("Also rename the branch on the remote, and update the upstream")
+                        .long("remote")
+                        .short('r')
+                        .takes_value(false),
+                ),
+        )
+        // Cherry-pick
+        .subcommand(
+            App::new("cherry-pick")
+                .alias("cp")
+                .about("Applies the changes from existing commits. Will not start if there are uncommitted changes.")
+                .arg(
+                    Arg::new("refs")
+                        .help("The commits to cherry-pick")
+                        .multiple_values(true)
+                        .required_unless_present_any(["abort", "continue"]),
+                )
+                .arg(
+                    Arg::new("abort")
+                        .help("Abort the cherry-pick in progress")
+                        .long("abort")
+                        .takes_value(false)
+                        .conflicts_with_all(&["refs", "continue"]),
+                )
+                .arg(
+                    Arg::new("continue")
+                        .help("Continue the cherry-pick in progress")
+                        .long("continue")
+                        .takes_value(false)
+                        .conflicts_with("refs"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .help("Cherry-pick even if there are uncommitted changes")
+                        .long("force")
+                        .short('f')
+                        .takes_value(false),
+                ),
+        )
+        // Tag
+        .subcommand(
+            App::new("tag")
+                .alias("t")
+                .about("Creates an annotated tag")
+                .arg(
+                    Arg::new("name")
+                        .help("The name of the tag, eg. v1.2.0")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("message")
+                        .help("The tag message. Defaults to the tag name")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("push")
+                        .help("Push the tag to origin after creating it")
+                        .long("push")
+                        .short('p')
+                        .takes_value(false),
+                ),
+        )
+        // Status
+        .subcommand(
+            App::new("status")
+                .alias("st")
+                .about("Lists staged, unstaged and untracked files")
+                .arg(
+                    Arg::new("stat")
+                        .help("Show how many lines were added and removed in each file")
+                        .long("stat")
+                        .takes_value(false),
+                ),
+        )
+        // Conflicts
+        .subcommand(
+            App::new("conflicts")
+                .about("Lists files with unresolved conflicts")
+                .arg(
+                    Arg::new("stat")
+                        .help("Show how many conflicting hunks are left in each file")
+                        .long("stat")
+                        .takes_value(false),
+                ),
+        )
+        // Remote
+        .subcommand(
+            App::new("remote")
+                .about("Lists remotes and their URLs")
+                .subcommand(
+                    App::new("add")
+                        .about("Adds a remote")
+                        .arg(Arg::new("name").help("The name of the remote").required(true))
+                        .arg(Arg::new("url").help("The URL of the remote").required(true)),
+                )
+                .subcommand(
+                    App::new("remove")
+                        .about("Removes a remote")
+                        .arg(Arg::new("name").help("The name of the remote").required(true)),
+                ),
+        )
+        // Whoami
+        .subcommand(
+            App::new("whoami").about("Shows the git identity that commits will be made with"),
+        )
+        // Config
+        .subcommand(
+            App::new("config")
+                .about("Creates or shows the .qit.toml config")
+                .subcommand(
+                    App::new("init")
+                        .about("Writes a .qit.toml with the default settings")
+                        .arg(
+                            Arg::new("force")
+                                .help("Overwrite an existing .qit.toml")
+                                .long("force")
+                                .short('f')
+                                .takes_value(false),
+                        ),
+                )
+                .subcommand(App::new("show").about("Prints the effective config, defaults included")),
+        )
+        // Completions
+        .subcommand(
+            App::new("completions")
+                .about("Generates shell completions")
+                .arg(
+                    Arg::new("shell")
+                        .help("The shell to generate completions for")
+                        .possible_values(["bash", "zsh", "fish"])
+                        .required(true),
+                ),
+        )
+        // Emoji, for scripts and prompts
+        .subcommand(
+            App::new("emoji")
+                .about("Prints the emoji for a commit type")
+                .hide(true)
+                .arg(
+                    Arg::new("type")
+                        .help("The type of commit")
+                        .required(true),
+                ),
+        )
+}
+
+fn handle<T>(res: Result<T>) -> T {
+    match res {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("💥 Unable to run command:");
+            eprintln!("{}", err);
+            let code = err
+                .downcast_ref::<GitError>()
+                .and_then(|err| err.status.code())
+                .unwrap_or(1);
+            std::process::exit(code);
+        }
+    }
+}
+
+fn commit_help(config: &Config) -> String {
+    let width = config
+        .types()
+        .iter()
+        .map(|type_| type_.len())
+        .max()
+        .unwrap_or(0);
+    let emojis = config
+        .emojis()
+        .iter()
+        .map(|(type_, emoji)| format!("    {:>width$} {}", type_, emoji, width = width))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let aliases = config
+        .aliases()
+        .map(|(alias, type_)| format!("{} -> {}", alias, type_))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    format!(
+        "
+Format:
+    <emoji> <type>[(<area>)]: <message>
+
+Emojis:
+{}
+
+    Emojis inspired by https://gitmoji.dev/
+    Emojis can be customised in {}
+
+Aliases:
+    {}
+
+Staging:
+    By default, all changes are staged with `git add -A`, including new
+    untracked files. With --tracked-only, only modifications and deletions of
+    files that are already tracked are staged, with `git add -u`. With
+    --paths, only the given paths are staged. With --no-add, nothing is staged
+    and only already-staged changes are committed.
+
+Examples:
+    ✨ feature: Add thing
+    ✨ feature(cli): Improve args
+    🔨 chore: Do thing
+    🚀 deploy(api): Deploy to production
+",
+        emojis,
+        config::CONFIG_FILE,
+        aliases
+    )
+}
+
+/// Whether the subcommand reads `.qit.toml`, so can't run if it's broken.
+fn uses_config(matches: &ArgMatches) -> bool {
+    match matches.subcommand() {
+        Some(("config", args)) => args.subcommand_name() != Some("init"),
+        Some((name, _)) => matches!(
+            name,
+            "init" | "commit" | "wip" | "amend" | "squash" | "tag" | "emoji"
+        ),
+        None => false,
+    }
+}
+
+// Subcommands //
+
+const DEFAULT_MAX_SUBJECT: usize = 72;
+
+#[derive(Debug, Default)]
+struct CommitOptions<'a> {
+    format: SubjectFormat,
+    area: Option<&'a str>,
+    scope_from_branch: bool,
+    body: Vec<&'a str>,
+    breaking: bool,
+    breaking_desc: Option<&'a str>,
+    author: Option<&'a str>,
+    co_authors: Vec<&'a str>,
+    trailers: Vec<&'a str>,
+    tracked_only: bool,
+    no_add: bool,
+    paths: Vec<&'a str>,
+    max_subject: usize,
+    count_emoji: bool,
+    strict: bool,
+    show_staged: bool,
+    print_only: bool,
+    no_verify: bool,
+    /// Replace HEAD rather than adding a new commit.
+    amend: bool,
+    sign: bool,
+    /// Set by `--no-sign`, to skip signing even if git would sign by default.
+    no_sign: bool,
+    signoff: bool,
+}
+
+impl CommitOptions<'_> {
+    /// The options before any flags are applied, taking defaults from the
+    /// environment and config.
+    fn new(config: &Config) -> Self {
+        CommitOptions {
+            format: SubjectFormat::new(config),
+            max_subject: DEFAULT_MAX_SUBJECT,
+            sign: env_enabled("QIT_SIGN") || git_config_enabled("commit.gpgsign"),
+            signoff: env_enabled("QIT_SIGNOFF"),
+            ..Default::default()
+        }
+    }
+}
+
+fn commit(config: &Config, type_: &str, message: &str, options: &CommitOptions) -> Result<()> {
+    let message = commit_message(config, type_, message, options)?;
+    if options.print_only {
+        println!("{}", message.subject);
+        return Ok(());
+    }
+    create_commit(config, &message, options)
+}
+
+/// A formatted commit message that passed every check from the config.
+#[derive(Debug)]
+struct CommitMessage {
+    subject: String,
+    /// Anything after the first line of the message.
+    rest: Option<String>,
+    template: Option<String>,
+}
+
+impl CommitMessage {
+    /// `-m` arguments for the body: the rest of the message, then `extra`
+    /// paragraphs, then the template.
+    fn body_args<'a>(&'a self, extra: &[&'a str]) -> Vec<&'a str> {
+        let template = self.template.as_deref().map(str::trim);
+        self.rest
+            .as_deref()
+            .into_iter()
+            .chain(extra.iter().copied())
+            .chain(template.filter(|template| !template.is_empty()))
+            .flat_map(|paragraph| ["-m", paragraph])
+            .collect()
+    }
+}
+
+/// Formats the subject and body for a commit, checking them against the
+/// config without touching the repository.
+fn commit_message(
+    config: &Config,
+    type_: &str,
+    message: &str,
+    options: &CommitOptions,
+) -> Result<CommitMessage> {
+    let branch_area = match options.area {
+        None if options.scope_from_branch => branch_scope()?,
+        _ => None,
+    };
+    let area = options
+        .area
+        .or(branch_area.as_deref())
+        .or_else(|| config.default_scope(type_));
+    // Only the first line of the message is the subject, anything after it
+    // becomes the first paragraph of the body.
+    let (message, rest) = match message.trim().split_once('\n') {
+        Some((subject, rest)) => (subject.trim(), Some(rest.trim())),
+        None => (message.trim(), None),
+    };
+    let message = match branch_prefix(config)? {
+        // Don't add it twice if it was typed out already
+        Some(prefix) if !message.starts_with(&prefix) => {
+            Cow::Owned(format!("{} {}", prefix, message))
+        }
+        _ => Cow::Borrowed(message),
+    };
+    let message = &*message;
+    let formatted = format_subject(
+        config,
+        options.format,
+        type_,
+        &area,
+        message,
+        options.breaking,
+    )?;
+    check_subject_length(config, type_, &formatted, options)?;
+    if let Some(pattern) = config.subject_pattern() {
+        if !pattern.is_match(&formatted) {
+            return Err(IOError::other(format!(
+                "Subject `{}` doesn't match the pattern `{}` from {}",
+                formatted,
+                pattern,
+                config::CONFIG_FILE
+            ))
+            .into());
+        }
+    }
+    let template = match config.template() {
+        Some(template) => Some(render_template(template, type_, area, message)?),
+        None => None,
+    };
+    Ok(CommitMessage {
+        subject: formatted,
+        rest: rest.filter(|rest| !rest.is_empty()).map(str::to_string),
+        template,
+    })
+}
+
+/// Stages changes and commits them with an already checked message.
+fn create_commit(config: &Config, message: &CommitMessage, options: &CommitOptions) -> Result<()> {
+    check_can_commit()?;
+    let mut trailers = vec![];
+    match options.breaking_desc {
+        Some(desc) => trailers.push(format!("BREAKING CHANGE: {}", desc)),
+        None if options.breaking => {
+            eprintln!("⚠️  --breaking was given without --breaking-desc, so no BREAKING CHANGE footer will be added");
+        }
+        None => (),
+    }
+    let author = match options.author {
+        Some(author) => {
+            validate_identity(author)?;
+            Some(format!("--author={}", author))
+        }
+        None => None,
+    };
+    for co_author in &options.co_authors {
+        validate_identity(co_author)?;
+        trailers.push(format!("Co-authored-by: {}", co_author));
+    }
+    for trailer in &options.trailers {
+        trailers.push(parse_trailer(trailer)?);
+    }
+
+    if options.show_staged {
+        let files = files_to_commit(config, options)?;
+        // Amending can just change the message
+        if files.is_empty() && !options.amend {
+            return Err(IOError::other("There is nothing to commit").into());
+        }
+        println!("These files will be committed:");
+        for file in &files {
+            println!("    {}", paint(GREEN, file));
+        }
+        if !is_dry_run() && !confirm("Commit them?", "Leave out --show-staged")? {
+            return Err(IOError::other("Not committing").into());
+        }
+    }
+    stage_for_commit(config, options)?;
+
+    let mut args = vec!["commit"];
+    if options.amend {
+        args.push("--amend");
+    }
+    if options.no_verify {
+        args.push("--no-verify");
+    }
+    if options.sign {
+        args.push("-S");
+    } else if options.no_sign {
+        args.push("--no-gpg-sign");
+    }
+    if options.signoff {
+        args.push("-s");
+    }
+    args.extend(author.as_deref());
+
+    // `-a` would stage excluded files that are already tracked
+    if options.no_add || !options.paths.is_empty() || !config.commit_exclude().is_empty() {
+        args.extend(["-m", &message.subject]);
+    } else {
+        args.extend(["-am", &message.subject]);
+    }
+    args.extend(message.body_args(&options.body));
+    let trailers = trailers.join("\n");
+    if !trailers.is_empty() {
+        args.extend(["-m", &trailers]);
+    }
+    // A rejected pre-commit hook shows up as a non-zero exit status
+    run_git_quiet(&args)
+}
+
+fn init(config: &Config, branch: Option<&str>, initial_commit: bool) -> Result<()> {
+    let mut args = vec!["init"];
+    if let Some(branch) = branch {
+        args.extend(["-b", branch]);
+    }
+    run_git_checked(&args)?;
+
+    if initial_commit {
+        let format = SubjectFormat::new(config);
+        let subject = format_subject(config, format, "chore", &None, "Initial commit", false)?;
+        run_git_checked(&["commit", "--allow-empty", "-m", &subject])?;
+    }
+    Ok(())
+}
+
+/// Refuses to commit partway through another operation, and warns when the
+/// commit won't be on a branch.
+fn check_can_commit() -> Result<()> {
+    check_no_operation_in_progress()?;
+    if head_detached()? {
+        eprintln!("⚠️  HEAD is detached, this commit won't be on any branch");
+    }
+    Ok(())
+}
+
+/// Splits the `--paths` values into the paths themselves and anything after
+/// them, which is the type and message. A path has to exist, or be tracked when
+/// it's been deleted.
+fn commit_paths(args: &ArgMatches) -> Result<(Vec<&str>, Vec<&str>)> {
+    let values: Vec<&str> = match args.values_of("paths") {
+        Some(values) => values.collect(),
+        None => return Ok((vec![], vec![])),
+    };
+    let repo = open_repo()?;
+    let index = repo.index()?;
+    // Index paths are relative to the top of the repository
+    let workdir = repo.workdir().map(fs::canonicalize).transpose()?;
+    let cwd = env::current_dir()?.canonicalize()?;
+    let prefix = workdir
+        .as_deref()
+        .and_then(|workdir| cwd.strip_prefix(workdir).ok())
+        .unwrap_or(Path::new(""));
+    let is_path = |value: &&str| {
+        Path::new(value).exists() || index.get_path(&prefix.join(value), 0).is_some()
+    };
+    let split = values.iter().position(|value| !is_path(value));
+    let (paths, rest) = values.split_at(split.unwrap_or(values.len()));
+    if paths.is_empty() {
+        return Err(IOError::other(format!("No such path: {}", values[0])).into());
+    }
+    Ok((paths.to_vec(), rest.to_vec()))
+}
+
+/// Reads the `commit` flags on top of the defaults from the environment and
+/// config.
+fn commit_options<'a>(config: &Config, args: &'a ArgMatches) -> CommitOptions<'a> {
+    let mut options = CommitOptions::new(config);
+    options.format.conventional |= args.is_present("conventional");
+    options.format.emoji_suffix |= args.is_present("emoji-suffix");
+    options.format.no_emoji = args.is_present("no-emoji");
+    options.area = args.value_of("area");
+    options.scope_from_branch = args.is_present("scope-from-branch");
+    options.body = args
+        .values_of("body")
+        .map(|v| v.collect())
+        .unwrap_or_default();
+    options.breaking = args.is_present("breaking");
+    options.breaking_desc = args.value_of("breaking-desc");
+    options.author = args.value_of("author");
+    options.co_authors = args
+        .values_of("co-author")
+        .map(|v| v.collect())
+        .unwrap_or_default();
+    options.trailers = args
+        .values_of("trailer")
+        .map(|v| v.collect())
+        .unwrap_or_default();
+    options.tracked_only = args.is_present("tracked-only");
+    options.no_add = args.is_present("no-add");
+    options.paths = handle(commit_paths(args)).0;
+    if let Some(max_subject) = args.value_of("max-subject") {
+        options.max_subject = handle(parse_positive("max-subject", max_subject));
+    }
+    options.count_emoji = args.is_present("count-emoji");
+    options.strict = args.is_present("strict");
+    options.print_only = args.is_present("print-only");
+    options.show_staged = args.is_present("show-staged");
+    options.no_verify = args.is_present("no-verify");
+    options.amend = args.is_present("amend");
+    options.sign |= args.is_present("sign");
+    if args.is_present("no-sign") {
+        options.sign = false;
+        options.no_sign = true;
+    }
+    options.signoff |= args.is_present("signoff");
+    options
+}
+
+/// Makes a `fixup!` or `squash!` commit for `git rebase --autosquash`, given
+/// `--fixup=<commit>` or `--squash=<commit>`. git writes the message.
+fn fixup_commit(config: &Config, fixup: &str, options: &CommitOptions) -> Result<()> {
+    check_can_commit()?;
+    if let Some(author) = options.author {
+        validate_identity(author)?;
+    }
+    let author = options.author.map(|author| format!("--author={}", author));
+    stage_for_commit(config, options)?;
+
+    let mut args = vec!["commit"];
+    if options.no_verify {
+        args.push("--no-verify");
+    }
+    if options.sign {
+        args.push("-S");
+    } else if options.no_sign {
+        args.push("--no-gpg-sign");
+    }
+    if options.signoff {
+        args.push("-s");
+    }
+    args.extend(author.as_deref());
+    args.push(fixup);
+    run_git_quiet(&args)
+}
+
+/// The canonical form of the given type, or the default type, or else one
+/// the user picks.
+fn resolve_type(config: &Config, type_: Option<&str>) -> Result<String> {
+    if let Some(type_) = type_ {
+        return Ok(config.canonical_type(type_).to_string());
+    }
+    match default_type(config)? {
+        Some(type_) => Ok(type_),
+        None => Ok(select_type(config)?.to_string()),
+    }
+}
+
+/// Asks the user to pick a commit type from a numbered list.
+fn select_type(config: &Config) -> Result<&str> {
+    if !std::io::stdin().is_terminal() {
+        return Err(IOError::other("No commit type given, and stdin isn't interactive").into());
+    }
+
+    let types = config.emojis();
+    eprintln!("Select a commit type:");
+    for (i, (type_, emoji)) in types.iter().enumerate() {
+        eprintln!("{:>4}) {} {}", i + 1, emoji, type_);
+    }
+    loop {
+        eprint!("> ");
+        std::io::stderr().flush()?;
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line)? == 0 {
+            return Err(IOError::other("No commit type selected").into());
+        }
+
+        let line = line.trim();
+        let selected = match line.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= types.len() => Some(types[n - 1].0.as_str()),
+            _ => {
+                let canonical = config.canonical_type(line);
+                types
+                    .iter()
+                    .map(|(type_, _)| type_.as_str())
+                    .find(|type_| *type_ == canonical)
+            }
+        };
+        match selected {
+            Some(type_) => return Ok(type_),
+            None => eprintln!("Pick a number from 1 to {}, or a type name", types.len()),
+        }
+    }
+}
+
+/// Asks a yes/no question, defaulting to no. `hint` says how to avoid the
+/// question when stdin isn't interactive.
+fn confirm(prompt: &str, hint: &str) -> Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        return Err(IOError::other(format!(
+            "Unable to confirm, stdin isn't interactive. {}",
+            hint
+        ))
+        .into());
+    }
+    eprint!("{} [y/N] ", prompt);
+    std::io::stderr().flush()?;
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    Ok(matches!(line.trim(), "y" | "Y" | "yes"))
+}
+
+/// Stages the given paths, or everything `stage_changes` would, unless
+/// `--no-add` was passed.
+fn stage_for_commit(config: &Config, options: &CommitOptions) -> Result<()> {
+    if !options.paths.is_empty() {
+        let mut args = vec!["add", "--"];
+        args.extend(&options.paths);
+        run_git_quiet(&args)
+    } else if options.no_add {
+        Ok(())
+    } else {
+        stage_changes(config, options.tracked_only)
+    }
+}
+
+/// The files that will be in the commit once [`stage_for_commit`] has run.
+fn files_to_commit(config: &Config, options: &CommitOptions) -> Result<Vec<String>> {
+    let repo = open_repo()?;
+    let exclude = git2::Pathspec::new(config.commit_exclude())?;
+    // An empty pathspec matches everything
+    let excluded = |path: &str| {
+        !config.commit_exclude().is_empty()
+            && exclude.matches_path(Path::new(path), git2::PathspecFlags::DEFAULT)
+    };
+    let mut files = vec![];
+    // List the files inside new directories, as some of them may be excluded
+    let mut status_options = status_options();
+    status_options.recurse_untracked_dirs(true);
+    for entry in repo.statuses(Some(&mut status_options))?.iter() {
+        let status = entry.status();
+        let path = entry.path().unwrap_or_default();
+        let staged = index_change(status).is_some();
+        let will_stage = if !options.paths.is_empty() {
+            options.paths.iter().any(|given| {
+                let given = given.trim_end_matches('/');
+                path == given || path.starts_with(&format!("{}/", given))
+            })
+        } else if options.no_add || excluded(path) {
+            false
+        } else if options.tracked_only {
+            worktree_change(status).is_some()
+        } else {
+            status.is_wt_new() || worktree_change(status).is_some()
+        };
+        if !status.is_ignored() && (staged || will_stage) {
+            files.push(path.to_string());
+        }
+    }
+    Ok(files)
+}
+
+/// Stages all changes, or only changes to tracked files, leaving out the
+/// `[commit] exclude` paths.
+fn stage_changes(config: &Config, tracked_only: bool) -> Result<()> {
+    let mut args = vec!["add", if tracked_only { "-u" } else { "-A" }];
+    // Relative to the top of the repository, like the config file
+    let exclude: Vec<String> = config
+        .commit_exclude()
+        .iter()
+        .map(|pattern| format!(":(top,exclude){}", pattern))
+        .collect();
+    if !exclude.is_empty() {
+        args.extend(["--", ":/"]);
+        args.extend(exclude.iter().map(String::as_str));
+    }
+    run_git_quiet(&args)
+}
+
+fn read_stdin() -> Result<String> {
+    let mut message = String::new();
+    std::io::stdin().read_to_string(&mut message)?;
+    Ok(message)
+}
+
+/// Opens `$EDITOR`, falling back to git's configured editor, to write a
+/// commit message. Lines starting with `#` are dropped.
+fn edit_message() -> Result<String> {
+    let repo = open_repo()?;
+    let path = repo.path().join("QIT_EDITMSG");
+    fs::write(
+        &path,
+        "\n# Enter the commit message. The first line is the subject, and will be\n# formatted with the commit type. Lines starting with '#' are ignored.\n",
+    )?;
+
+    let editor = match env::var("EDITOR") {
+        Ok(editor) if !editor.is_empty() => editor,
+        _ => git_query(&["var", "GIT_EDITOR"])?,
+    };
+    if editor.is_empty() {
+        return Err(IOError::other("No editor configured, set $EDITOR or core.editor").into());
+    }
+
+    // Run through the shell, as editors are commonly configured with
+    // arguments, e.g. `code --wait`.
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(&editor)
+        .arg(&path)
+        .status()?;
+    if !status.success() {
+        return Err(IOError::other(format!("Editor `{}` exited with {}", editor, status)).into());
+    }
+
+    let message = fs::read_to_string(&path)?
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect::<Vec<&str>>()
+        .join("\n");
+    if message.trim().is_empty() {
+        return Err(IOError::other("Aborting commit due to empty commit message").into());
+    }
+    Ok(message)
+}
+
+fn check_subject_length(
+    config: &Config,
+    type_: &str,
+    formatted: &str,
+    options: &CommitOptions,
+) -> Result<()> {
+    let measured = if options.count_emoji {
+        formatted
+    } else {
+        let emoji = emoji(config, type_)?;
+        formatted
+            .strip_prefix(&*emoji)
+            .or_else(|| formatted.strip_suffix(&*emoji))
+            .unwrap_or(formatted)
+            .trim()
+    };
+    let length = measured.chars().count();
+    if length <= options.max_subject {
+        return Ok(());
+    }
+
+    let warning = format!(
+        "Subject is {} characters long, which is more than {}",
+        length, options.max_subject
+    );
+    if options.strict {
+        Err(IOError::other(warning).into())
+    } else {
+        eprintln!("⚠️  {}", warning);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct AmendOptions<'a> {
+    area: Option<&'a str>,
+    reword: bool,
+    no_edit: bool,
+    no_add: bool,
+}
+
+fn amend(
+    config: &Config,
+    type_: Option<&str>,
+    message: Option<&str>,
+    options: &AmendOptions,
+) -> Result<()> {
+    // The same checks as `commit`, so both ways of amending agree
+    let message = match (type_, message) {
+        (Some(type_), Some(message)) => {
+            let mut commit_options = CommitOptions::new(config);
+            commit_options.area = options.area;
+            Some(commit_message(config, type_, message, &commit_options)?)
+        }
+        _ => None,
+    };
+
+    let mut args = vec!["commit", "--amend"];
+    if options.reword {
+        // Without paths, `--only` leaves the index out of the commit
+        args.push("--only");
+    }
+    if options.no_edit {
+        if !options.no_add {
+            stage_changes(config, false)?;
+        }
+        args.push("--no-edit");
+    }
+    if let Some(message) = &message {
+        args.extend(["-m", &message.subject]);
+        args.extend(message.body_args(&[]));
+    }
+    run_git_checked(&args)
+}
+
+#[derive(Debug, Default)]
+struct LogOptions<'a> {
+    short: bool,
+    max_count: Option<usize>,
+    since: Option<&'a str>,
+    until: Option<&'a str>,
+    all: bool,
+    graph: bool,
+}
+
+fn log(options: &LogOptions) -> Result<()> {
+    let max_count = options.max_count.map(|n| n.to_string());
+    let since = options.since.map(|since| format!("--since={}", since));
+    let until = options.until.map(|until| format!("--until={}", until));
+
+    let mut args = paged("log");
+    if options.short {
+        args.push("--oneline");
+    }
+    if options.all {
+        args.push("--all");
+    }
+    if options.graph {
+        args.push("--graph");
+    }
+    if options.all || options.graph {
+        args.push("--decorate");
+    }
+    if let Some(max_count) = &max_count {
+        args.extend(["-n", max_count]);
+    }
+    args.extend(since.as_deref());
+    args.extend(until.as_deref());
+    run_git_piped(&args)
+}
+
+/// Counts the commits [`log`] would show, like `git rev-list --count`.
+fn log_count(options: &LogOptions) -> Result<()> {
+    // git knows how to read dates like `2 weeks ago`, so let it turn them
+    // into timestamps
+    let timestamp = |flag: &str, date: Option<&str>, prefix: &str| -> Result<Option<i64>> {
+        let date = match date {
+            Some(date) => date,
+            None => return Ok(None),
+        };
+        let parsed = git_query(&["rev-parse", &format!("--{}={}", flag, date)])?;
+        match parsed.strip_prefix(prefix).map(str::parse) {
+            Some(Ok(timestamp)) => Ok(Some(timestamp)),
+            _ => Err(IOError::other(format!("Invalid --{} date `{}`", flag, date)).into()),
+        }
+    };
+    let since = timestamp("since", options.since, "--max-age=")?;
+    let until = timestamp("until", options.until, "--min-age=")?;
+
+    let repo = open_repo()?;
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_head()?;
+    if options.all {
+        revwalk.push_glob("*")?;
+    }
+    let mut count = 0;
+    for oid in revwalk {
+        let time = repo.find_commit(oid?)?.time().seconds();
+        if since.is_none_or(|since| time >= since) && until.is_none_or(|until| time <= until) {
+            count += 1;
+        }
+    }
+    println!("{}", options.max_count.map_or(count, |max| count.min(max)));
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct LogEntry {
+    hash: String,
+    author_name: String,
+    author_email: String,
+    timestamp: i64,
+    summary: String,
+}
+
+fn log_json(max_count: Option<usize>) -> Result<()> {
+    let repo = open_repo()?;
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_head()?;
+
+    let mut entries = vec![];
+    for oid in revwalk.take(max_count.unwrap_or(usize::MAX)) {
+        let commit = repo.find_commit(oid?)?;
+        let author = commit.author();
+        entries.push(LogEntry {
+            hash: commit
+                .as_object()
+                .short_id()?
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            author_name: author.name().unwrap_or_default().to_string(),
+            author_email: author.email().unwrap_or_default().to_string(),
+            timestamp: commit.time().seconds(),
+            summary: commit.summary().unwrap_or_default().to_string(),
+        });
+    }
+
+    println!("{}", serde_json::to_string(&entries)?);
+    Ok(())
+}
+
+fn last(oneline: bool) -> Result<()> {
+    let repo = open_repo()?;
+    let commit = repo.head()?.peel_to_commit()?;
+    if oneline {
+        println!("{}", commit.summary().unwrap_or_default());
+        return Ok(());
+    }
+    let author = commit.author();
+    println!("{}", paint(YELLOW, &format!("commit {}", commit.id())));
+    println!(
+        "Author: {} <{}>",
+        author.name().unwrap_or_default(),
+        author.email().unwrap_or_default()
+    );
+    println!("Date:   {}", format_time(author.when()));
+    println!();
+    for line in commit.message().unwrap_or_default().trim_end().lines() {
+        println!("    {}", line);
+    }
+    Ok(())
+}
+
+/// Formats a commit time like `2021-12-20 14:03:11 +0100`, in its own
+/// timezone.
+fn format_time(time: git2::Time) -> String {
+    let offset = i64::from(time.offset_minutes());
+    let local = time.seconds() + offset * 60;
+    let (days, secs) = (local.div_euclid(86400), local.rem_euclid(86400));
+
+    // Days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} {}{:02}{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+        if offset < 0 { '-' } else { '+' },
+        offset.abs() / 60,
+        offset.abs() % 60
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Force {
+    None,
+    /// `git push --force-with-lease`
+    Lease,
+    /// `git push --force`
+    Hard,
+}
+
+#[derive(Debug)]
+struct PushOptions<'a> {
+    force: Force,
+    remote: Option<&'a str>,
+    set_upstream: bool,
+    /// `git push --dry-run`, unlike `qit --dry-run` this still talks to the
+    /// remote.
+    dry_run: bool,
+    /// Whether to refuse up front when the branch is behind its upstream.
+    check: bool,
+}
+
+fn push(options: &PushOptions) -> Result<()> {
+    let PushOptions { force, remote, .. } = *options;
+    check_no_operation_in_progress()?;
+    if pending_changes()? && force == Force::None {
+        return Err(IOError::other("There are uncommitted changes").into());
+    }
+    // Fail with a clear message rather than git's non-fast-forward rejection
+    if options.check && force == Force::None {
+        let behind = commits_behind_upstream(remote)?;
+        if behind > 0 {
+            return Err(IOError::other(format!(
+                "You are {} commit(s) behind the upstream, so the push will be rejected. Pull first",
+                behind
+            ))
+            .into());
+        }
+    }
+    let mut args = vec!["push"];
+    if options.dry_run {
+        args.push("--dry-run");
+    }
+    match force {
+        Force::None => (),
+        Force::Lease => args.push("--force-with-lease"),
+        Force::Hard => args.push("--force"),
+    }
+    // Pushing a new branch fails without an upstream, so set one up
+    let new_branch = if options.set_upstream {
+        branch_without_upstream()?
+    } else {
+        None
+    };
+    match (&new_branch, remote) {
+        (Some(branch), remote) => {
+            args.extend(["--set-upstream", remote.unwrap_or("origin"), branch])
+        }
+        (None, Some(remote)) => args.push(remote),
+        (None, None) => (),
+    }
+    if is_quiet() {
+        run_git_quiet(&args)?;
+    } else {
+        run_git_spinner("Pushing", &args)?;
+    }
+
+    if !is_quiet() && !is_dry_run() && !options.dry_run {
+        let repo = open_repo()?;
+        if let Some(branch) = current_branch(&repo) {
+            let remote = match remote {
+                Some(remote) => remote.to_string(),
+                None => upstream_remote(&repo, &branch).unwrap_or_else(|| "origin".to_string()),
+            };
+            println!("🚀 Pushed {} to {}", branch, remote);
+        }
+    }
+    Ok(())
+}
+
+fn pull(merge: bool, autostash: bool) -> Result<()> {
+    if pending_changes()? && !autostash {
+        return Err(IOError::other("There are uncommitted changes").into());
+    }
+    let mut args = vec!["pull"];
+    if merge {
+        args.push("--no-rebase");
+    } else {
+        args.push("--rebase");
+    }
+    if autostash {
+        args.push("--autostash");
+    }
+    run_git_spinner("Pulling", &args)
+}
+
+fn fetch(all: bool, prune: bool) -> Result<()> {
+    let mut args = vec!["fetch"];
+    if all {
+        args.push("--all");
+    }
+    if prune {
+        args.push("--prune");
+    }
+    run_git_spinner("Fetching", &args)
+}
+
+fn sync(force: Force) -> Result<()> {
+    pull(false, false)?;
+    push(&PushOptions {
+        force,
+        remote: None,
+        set_upstream: true,
+        dry_run: false,
+        check: true,
+    })
+}
+
+/// Where `undo` saves the message of the commit it removed, relative to `.git`.
+const LAST_UNDO_FILE: &str = "qit_last_undo";
+
+fn undo(count: usize, force: bool) -> Result<()> {
+    check_reset_count("undo", count)?;
+    // Undoing a pushed commit makes the branch diverge from its upstream
+    if !force && !is_dry_run() && commits_pushed(count)? {
+        let prompt = if count == 1 {
+            "The last commit has already been pushed. Undo it anyway?"
+        } else {
+            "Some of these commits have already been pushed. Undo them anyway?"
+        };
+        if !confirm(prompt, "Use --force")? {
+            return Err(IOError::other("Not undoing the commit").into());
+        }
+    }
+
+    // Keep the message of the undone commit around so that `redo` can
+    // recreate it. Several commits can't be told apart again once they're
+    // reset, so `redo` only works for one.
+    if !is_dry_run() {
+        let repo = open_repo()?;
+        let path = repo.path().join(LAST_UNDO_FILE);
+        if count == 1 {
+            let head = repo.head()?.peel_to_commit()?;
+            fs::write(path, head.message_bytes())?;
+        } else {
+            if path.is_file() {
+                fs::remove_file(path)?;
+            }
+            if !is_quiet() {
+                eprintln!(
+                    "⚠️  Undoing {} commits, which `qit redo` can't bring back",
+                    count
+                );
+            }
+        }
+    }
+
+    run_git_quiet(&["reset", "--soft", &format!("HEAD~{}", count)])
+}
+
+fn squash(config: &Config, count: usize, type_: Option<&str>, message: Option<&str>) -> Result<()> {
+    if count < 2 {
+        return Err(IOError::other("Squashing needs at least 2 commits").into());
+    }
+    check_reset_count("squash", count)?;
+    if pending_changes()? {
+        return Err(IOError::other("There are uncommitted changes").into());
+    }
+    let type_ = resolve_type(config, type_)?;
+    let message = match message {
+        Some(message) => message.to_string(),
+        None => edit_message()?,
+    };
+
+    let mut options = CommitOptions::new(config);
+    options.no_add = true;
+    // Check the message before resetting, so a rejected one loses nothing
+    let message = commit_message(config, &type_, &message, &options)?;
+    run_git_quiet(&["reset", "--soft", &format!("HEAD~{}", count)])?;
+    if let Err(err) = create_commit(config, &message, &options) {
+        // eg. a pre-commit hook failed, put the squashed commits back
+        run_git_quiet(&["reset", "--soft", "ORIG_HEAD"])?;
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Checks that `count` commits can be reset away, as the first commit can't be.
+fn check_reset_count(action: &str, count: usize) -> Result<()> {
+    // What `git reset HEAD~N` resets to, following first parents only
+    if open_repo()?
+        .revparse_single(&format!("HEAD~{}", count))
+        .is_err()
+    {
+        return Err(IOError::other(format!(
+            "Cannot {} {} commit(s), the branch doesn't have that many and the first commit can't be undone",
+            action, count
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+fn redo() -> Result<()> {
+    let repo = open_repo()?;
+    let path = repo.path().join(LAST_UNDO_FILE);
+    if !path.is_file() {
+        return Err(
+            IOError::other("Nothing to redo, only undoing a single commit can be redone").into(),
+        );
+    }
+
+    let args = ["commit", "-F", &path.to_string_lossy()];
+    let status = run_git(&args)?;
+    check_status(&git(&args), status)?;
+    if !is_dry_run() {
+        fs::remove_file(&path)?;
+    }
+    Ok(())
+}
+
+fn reflog(branch: Option<&str>, max_count: usize) -> Result<()> {
+    let max_count = max_count.to_string();
+    let mut args = paged("reflog");
+    args.push("show");
+    args.extend(branch);
+    args.extend(["-n", &max_count]);
+    run_git_piped(&args)
+}
+
+#[derive(Debug, Default)]
+struct RebaseOptions<'a> {
+    upstream: Option<&'a str>,
+    count: Option<usize>,
+    onto: Option<&'a str>,
+    autostash: bool,
+}
+
+fn rebase(options: &RebaseOptions) -> Result<()> {
+    if pending_changes()? && !options.autostash {
+        return Err(IOError::other("There are uncommitted changes").into());
+    }
+    let upstream = match options.count {
+        Some(count) => Some(format!("HEAD~{}", count)),
+        None => options.upstream.map(String::from),
+    };
+
+    let mut args = vec!["rebase", "-i"];
+    if options.autostash {
+        args.push("--autostash");
+    }
+    if let Some(onto) = options.onto {
+        args.extend(["--onto", onto]);
+    }
+    args.extend(upstream.as_deref());
+    run_git_checked(&args)
+}
+
+fn switch_branch(branch: &str) -> Result<()> {
+    if head_detached()? {
+        eprintln!("⚠️  HEAD is detached, commits that aren't on a branch will be left behind");
+    }
+    if branch == "-" {
+        if open_repo()?.revparse_single("@{-1}").is_err() {
+            return Err(IOError::other("There's no previous branch to switch back to").into());
+        }
+        return run_git_checked(&["checkout", "-"]);
+    }
+    let output = run_git_output(&["checkout", branch])?;
+    if output.status.success() {
+        return Ok(());
+    }
+
+    // Track the remote branch if there is one, rather than creating a new
+    // branch that diverges from it.
+    let remote = format!("origin/{}", branch);
+    let remote_exists = open_repo()?
+        .find_branch(&remote, git2::BranchType::Remote)
+        .is_ok();
+    if remote_exists {
+        run_git_checked(&["checkout", "-b", branch, "--track", &remote])
+    } else {
+        run_git_checked(&["checkout", "-b", branch])
+    }
+}
+
+fn restore(paths: &[&str], staged: bool, force: bool) -> Result<()> {
+    let mut args = vec!["restore"];
+    if staged {
+        args.push("--staged");
+    } else if !force && !is_dry_run() {
+        // Discarded changes can't be recovered
+        let prompt = format!("Discard unstaged changes to {}?", paths.join(", "));
+        if !confirm(&prompt, "Use --force")? {
+            return Err(IOError::other("Not discarding changes").into());
+        }
+    }
+    args.push("--");
+    args.extend(paths);
+    run_git_checked(&args)
+}
+
+fn diff(staged: bool, path: Option<&str>) -> Result<()> {
+    let mut args = paged("diff");
+    if staged {
+        args.push("--staged");
+    }
+    if let Some(path) = path {
+        args.extend(["--", path]);
+    }
+    run_git_piped(&args)
+}
+
+fn blame(file: &str, lines: Option<&str>) -> Result<()> {
+    if !Path::new(file).is_file() {
+        return Err(IOError::other(format!("No such file: {}", file)).into());
+    }
+    let mut args = paged("blame");
+    if let Some(lines) = lines {
+        args.extend(["-L", lines]);
+    }
+    args.extend(["--", file]);
+    run_git_piped(&args)
+}
+
+struct BranchListOptions {
+    remote: bool,
+    /// Plain names for scripts, see [`print_porcelain_branch`].
+    porcelain: bool,
+    current_marker: bool,
+}
+
+fn list_branches(options: &BranchListOptions) -> Result<()> {
+    let repo = open_repo()?;
+    let current = current_branch(&repo);
+    let filter = if options.remote {
+        None
+    } else {
+        Some(git2::BranchType::Local)
+    };
+
+    for branch in repo.branches(filter)? {
+        let (branch, _) = branch?;
+        let name = match branch.name()? {
+            Some(name) => name,
+            None => continue,
+        };
+        if options.porcelain {
+            print_porcelain_branch(name, branch.is_head(), options.current_marker);
+        } else if branch.is_head() {
+            println!("* {}", paint(GREEN, name));
+        } else if branch.get().is_remote() {
+            println!("  {}", paint(RED, name));
+        } else {
+            println!("  {}", name);
+        }
+    }
+    if current.is_none() && !options.porcelain {
+        println!("{}", paint(YELLOW, "(HEAD is detached)"));
+    }
+    Ok(())
+}
+
+/// Prints just the branch name, or with `current_marker` lines up the names
+/// behind a `*` or two spaces like `git branch` does.
+fn print_porcelain_branch(name: &str, is_head: bool, current_marker: bool) {
+    match (current_marker, is_head) {
+        (false, _) => println!("{}", name),
+        (true, true) => println!("* {}", name),
+        (true, false) => println!("  {}", name),
+    }
+}
+
+fn recent_branches(count: usize) -> Result<()> {
+    let repo = open_repo()?;
+    let reflog = repo.reflog("HEAD")?;
+    let mut recent: Vec<String> = Vec::new();
+    // Entries are newest first, and look like `checkout: moving from a to b`
+    for entry in reflog.iter() {
+        let to = match entry
+            .message()
+            .and_then(|message| message.strip_prefix("checkout: moving from "))
+        {
+            Some(message) => message.rsplit(" to ").next().unwrap_or_default(),
+            None => continue,
+        };
+        // Skip detached checkouts and branches that have since been deleted
+        let exists = repo.find_branch(to, git2::BranchType::Local).is_ok();
+        if exists && !recent.iter().any(|branch| branch == to) {
+            recent.push(to.to_string());
+        }
+        if recent.len() == count {
+            break;
+        }
+    }
+
+    let current = current_branch(&repo);
+    for branch in recent {
+        if current.as_deref() == Some(branch.as_str()) {
+            println!("* {}", paint(GREEN, &branch));
+        } else {
+            println!("  {}", branch);
+        }
+    }
+    Ok(())
+}
+
+fn delete_branch(branch: &str) -> Result<()> {
+    let repo = open_repo()?;
+    if current_branch(&repo).as_deref() == Some(branch) {
+        return Err(IOError::other(format!(
+            "Cannot delete `{}`, it is the currently checked-out branch",
+            branch
+        ))
+        .into());
+    }
+    run_git_checked(&["branch", "-d", branch])
+}
+
+fn prune_merged(into: &str, force: bool) -> Result<()> {
+    let repo = open_repo()?;
+    let target = repo
+        .find_branch(into, git2::BranchType::Local)
+        .map_err(|_| IOError::other(format!("No branch named {}", into)))?
+        .get()
+        .peel_to_commit()?
+        .id();
+    let current = current_branch(&repo);
+
+    let mut merged = Vec::new();
+    for branch in repo.branches(Some(git2::BranchType::Local))? {
+        let (branch, _) = branch?;
+        let name = match branch.name()? {
+            Some(name) if name != into && current.as_deref() != Some(name) => name.to_string(),
+            _ => continue,
+        };
+        let tip = branch.get().peel_to_commit()?.id();
+        if repo.merge_base(tip, target)? == tip {
+            merged.push(name);
+        }
+    }
+
+    if merged.is_empty() {
+        println!("No branches are merged into {}", into);
+        return Ok(());
+    }
+    for name in &merged {
+        if force {
+            // `-d` only accepts branches merged into HEAD, and `into` may not
+            // be checked out
+            run_git_checked(&["branch", "-D", name])?;
+        } else {
+            println!("Would delete {}", name);
+        }
+    }
+    Ok(())
+}
+
+fn add_worktree(path: &str, branch: &str) -> Result<()> {
+    let exists = open_repo()?
+        .find_branch(branch, git2::BranchType::Local)
+        .is_ok();
+    if exists {
+        run_git_checked(&["worktree", "add", path, branch])
+    } else {
+        run_git_checked(&["worktree", "add", "-b", branch, path])
+    }
+}
+
+/// Runs `git bisect <action>`, on `rev` if given.
+fn bisect(action: &str, rev: Option<&str>) -> Result<()> {
+    let mut args = vec!["bisect", action];
+    args.extend(rev);
+    run_git_checked(&args)
+}
+
+fn list_worktrees() -> Result<()> {
+    let repo = open_repo()?;
+    let current = repo.workdir().and_then(|dir| dir.canonicalize().ok());
+    // Blocks of `worktree <path>`, then `branch <ref>` or `detached`
+    let porcelain = git_query(&["worktree", "list", "--porcelain"])?;
+    for block in porcelain.split("\n\n") {
+        let mut path = None;
+        let mut branch = "(detached)";
+        for line in block.lines() {
+            if let Some(value) = line.strip_prefix("worktree ") {
+                path = Some(value);
+            } else if let Some(value) = line.strip_prefix("branch ") {
+                branch = value.strip_prefix("refs/heads/").unwrap_or(value);
+            } else if line == "bare" {
+                branch = "(bare)";
+            }
+        }
+        let path = match path {
+            Some(path) => path,
+            None => continue,
+        };
+        if Path::new(path).canonicalize().ok() == current {
+            println!("* {} {}", paint(GREEN, path), branch);
+        } else {
+            println!("  {} {}", path, branch);
+        }
+    }
+    Ok(())
+}
+
+fn stash(action: &str, message: Option<&str>) -> Result<()> {
+    let mut args = vec!["stash", action];
+    if let (Some(message), "push") = (message, action) {
+        args.extend(["-m", message]);
+    }
+    run_git_checked(&args)
+}
+
+fn clean(force: bool, ignored: bool) -> Result<()> {
+    // Only list what would go unless asked, as removed files can't be recovered
+    let mut args = vec!["clean", if force { "-fd" } else { "-nd" }];
+    if ignored {
+        args.push("-x");
+    }
+    run_git_checked(&args)
+}
+
+fn cherry_pick(refs: &[&str], force: bool) -> Result<()> {
+    if pending_changes()? && !force {
+        return Err(IOError::other("There are uncommitted changes").into());
+    }
+    let mut args = vec!["cherry-pick"];
+    args.extend(refs);
+    run_git_checked(&args)
+}
+
+fn tag(config: &Config, name: &str, message: &str, push: bool) -> Result<()> {
+    let emoji = emoji(config, "deploy")?;
+    let message = format!("{} {}", emoji, message);
+    run_git_checked(&["tag", "-a", name, "-m", message.trim()])?;
+    if push {
+        run_git_checked(&["push", "origin", name])?;
+    }
+    Ok(())
+}
+
+fn rename_branch(new: &str, remote: bool) -> Result<()> {
+    let repo = open_repo()?;
+    let old =
+        current_branch(&repo).ok_or_else(|| IOError::other("Cannot rename, HEAD is detached"))?;
+    // The branch on the remote to delete, which needn't have the same name
+    let upstream = upstream_remote(&repo, &old).and_then(|remote| {
+        let name = repo
+            .branch_upstream_name(&format!("refs/heads/{}", old))
+            .ok()?;
+        let branch = name
+            .as_str()?
+            .strip_prefix(&format!("refs/remotes/{}/", remote))?
+            .to_string();
+        Some((remote, branch))
+    });
+    let remote_name = match &upstream {
+        Some((remote, _)) => remote.clone(),
+        None => "origin".to_string(),
+    };
+
+    run_git_checked(&["branch", "-m", new])?;
+    if remote {
+        // Only delete the old name once the new one is safely pushed
+        run_git_checked(&["push", "--set-upstream", &remote_name, new])?;
+        if let Some((remote, branch)) = &upstream {
+            run_git_checked(&["push", remote, "--delete", branch])?;
+        }
+    }
+    if !is_dry_run() {
+        println!("Renamed {} to {}", old, paint(GREEN, new));
+    }
+    Ok(())
+}
 
-    let matches = app.get_matches();
+fn list_remotes() -> Result<()> {
+    let repo = open_repo()?;
+    for name in repo.remotes()?.iter().flatten() {
+        let remote = repo.find_remote(name)?;
+        let url = remote.url().unwrap_or_default();
+        match remote.pushurl() {
+            Some(push_url) if push_url != url => {
+                println!("{}\t{} (fetch)", name, url);
+                println!("{}\t{} (push)", name, push_url);
+            }
+            _ => println!("{}\t{}", name, url),
+        }
+    }
+    Ok(())
+}
 
-    match matches.subcommand() {
-        Some(("commit", args)) => {
-            let type_ = args.value_of("type").unwrap();
-            let area = args.value_of("area");
-            let message = args.value_of("message").unwrap();
-            let no_verify = args.is_present("no-verify");
-            handle(commit(type_, &area, message, no_verify));
+fn whoami() -> Result<()> {
+    let config = open_repo()?.config()?;
+    for key in ["user.name", "user.email"] {
+        match config.get_string(key) {
+            Ok(value) if !value.is_empty() => println!("{}: {}", key, value),
+            _ => eprintln!("⚠️  {} is not set, commits won't say who made them", key),
         }
-        Some(("log", args)) => {
-            let short = args.is_present("short");
-            handle(log(short));
+    }
+    Ok(())
+}
+
+fn conflicts(stat: bool) -> Result<()> {
+    let repo = open_repo()?;
+    let mut paths = vec![];
+    for conflict in repo.index()?.conflicts()? {
+        let conflict = conflict?;
+        // One side may be missing, eg. when a file was deleted on one branch
+        let entry = conflict.our.or(conflict.their).or(conflict.ancestor);
+        if let Some(entry) = entry {
+            paths.push(String::from_utf8_lossy(&entry.path).into_owned());
         }
-        Some(("push", args)) => {
-            let force = args.is_present("force");
-            handle(push(force));
+    }
+    if paths.is_empty() {
+        println!("✨ {}", paint(GREEN, "no conflicts"));
+        return Ok(());
+    }
+
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| IOError::other("This is a bare repository"))?;
+    for path in paths {
+        // A file deleted on our side has no markers to count
+        let hunks = match fs::read(workdir.join(&path)) {
+            Ok(contents) if stat => Some(
+                contents
+                    .split(|&b| b == b'\n')
+                    .filter(|line| line.starts_with(b"<<<<<<<"))
+                    .count(),
+            ),
+            _ => None,
+        };
+        match hunks {
+            Some(hunks) => println!("{} {}", paint(RED, &path), hunks),
+            None => println!("{}", paint(RED, &path)),
         }
-        Some(("undo", _)) => handle(undo()),
-        Some(("switch", args)) => handle(switch_branch(args.value_of("branch").unwrap())),
-        _ => println!("{}", repo_status()?),
     }
     Ok(())
 }
 
-fn handle(res: Result<()>) {
-    match res {
-        Ok(_) => (),
-        Err(err) => {
-            eprintln!("💥 Unable to run command:");
-            eprintln!("{}", err);
-            std::process::exit(1);
-        }
+fn status(stat: bool) -> Result<()> {
+    let repo = open_repo()?;
+    // The stats are per file, so list the files in new directories too
+    let mut status_options = status_options();
+    status_options.recurse_untracked_dirs(stat);
+    let statuses = repo.statuses(Some(&mut status_options))?;
+    let (staged_stats, unstaged_stats) = if stat {
+        line_stats(&repo)?
+    } else {
+        Default::default()
+    };
+    let with_stat = |line: String, path: &str, stats: &LineStats| match stats.get(path) {
+        Some((added, removed)) => format!(
+            "{} {} {}",
+            line,
+            paint(GREEN, &format!("+{}", added)),
+            paint(RED, &format!("-{}", removed))
+        ),
+        None => line,
     };
-}
 
-// Subcommands //
+    let mut staged = vec![];
+    let mut unstaged = vec![];
+    let mut untracked = vec![];
+    for entry in statuses.iter() {
+        let status = entry.status();
+        let path = entry.path().unwrap_or_default().to_string();
+        if status.is_ignored() {
+            continue;
+        }
+        if status.is_wt_new() {
+            untracked.push(with_stat(path.clone(), &path, &unstaged_stats));
+            continue;
+        }
+        if let Some(kind) = index_change(status) {
+            let line = format!("{}: {}", kind, path);
+            staged.push(with_stat(line, &path, &staged_stats));
+        }
+        if let Some(kind) = worktree_change(status) {
+            let line = format!("{}: {}", kind, path);
+            unstaged.push(with_stat(line, &path, &unstaged_stats));
+        }
+    }
 
-fn commit(type_: &str, area: &Option<&str>, message: &str, no_verify: bool) -> Result<()> {
-    // Emojis inspired by https://gitmoji.dev/
-    let emoji = match type_ {
-        "chore" => "🔨",
-        "feature" => "✨",
-        "refactor" => "♻️",
-        "fix" => "🐛",
-        "test" => "✅",
-        "style" => "🎨",
-        "doc" => "📝",
-        "deps" => "📦",
-        "deploy" => "🚀",
-        "wip" => "🚧",
-        _ => {
-            panic!("Unknown commit type")
+    for (title, color, files) in [
+        ("Staged", GREEN, &staged),
+        ("Unstaged", RED, &unstaged),
+        ("Untracked", YELLOW, &untracked),
+    ] {
+        if files.is_empty() {
+            continue;
         }
-    };
-    let emoji = match env::var("QIT_DISABLE_EMOJIS") {
-        Ok(value) => {
-            if value == "true" {
-                ""
-            } else {
-                emoji
-            }
+        println!("{}:", title);
+        for file in files {
+            println!("    {}", paint(color, file));
         }
-        _ => emoji,
+        println!();
+    }
+    println!("{} files changed", repo_status()?.total);
+    Ok(())
+}
+
+/// The lines added and removed in each file, by path.
+type LineStats = HashMap<String, (usize, usize)>;
+
+/// The line stats of the staged changes, and of the unstaged and untracked
+/// ones.
+fn line_stats(repo: &Repository) -> Result<(LineStats, LineStats)> {
+    let head = match repo.head() {
+        Ok(head) => Some(head.peel_to_tree()?),
+        // Nothing is committed yet, so everything is new
+        Err(_) => None,
     };
-    let formatted = match area {
-        Some(area) => format!("{} {}({}): {}", emoji, type_, area, message),
-        None => format!("{} {}: {}", emoji, type_, message),
+    let staged = repo.diff_tree_to_index(head.as_ref(), None, None)?;
+    let mut options = git2::DiffOptions::new();
+    options
+        .include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .show_untracked_content(true);
+    let unstaged = repo.diff_index_to_workdir(None, Some(&mut options))?;
+    Ok((diff_line_stats(&staged)?, diff_line_stats(&unstaged)?))
+}
+
+fn diff_line_stats(diff: &git2::Diff) -> Result<LineStats> {
+    let mut stats = HashMap::new();
+    for i in 0..diff.deltas().len() {
+        let patch = match git2::Patch::from_diff(diff, i)? {
+            Some(patch) => patch,
+            None => continue,
+        };
+        let path = patch
+            .delta()
+            .new_file()
+            .path()
+            .map(|path| path.to_string_lossy().into_owned());
+        if let Some(path) = path {
+            let (_, added, removed) = patch.line_stats()?;
+            stats.insert(path, (added, removed));
+        }
+    }
+    Ok(stats)
+}
+
+fn completions(app: &mut App, shell: &str) -> Result<()> {
+    let shell: Shell = shell.parse()?;
+    clap_complete::generate(shell, app, "qit", &mut std::io::stdout());
+    Ok(())
+}
+
+// Helpers //
+
+/// Runs git with the given arguments, inheriting stdio so that interactive
+/// commands (editors, pagers) work as usual. Under `--dry-run`, the command is
+/// printed instead and reported as successful.
+fn run_git(args: &[&str]) -> Result<ExitStatus> {
+    let mut cmd = git(args);
+    if dry_run(&cmd) {
+        return Ok(ExitStatus::default());
+    }
+    let mut child = cmd.spawn().map_err(|err| spawn_error(&cmd, err))?;
+    Ok(child.wait()?)
+}
+
+/// Like [`run_git`], but fails if git exits with a non-zero status.
+fn run_git_checked(args: &[&str]) -> Result<()> {
+    let status = run_git(args)?;
+    check_status(&git(args), status)
+}
+
+/// Like [`run_git_checked`], but discards git's output under `--quiet`.
+fn run_git_quiet(args: &[&str]) -> Result<()> {
+    if !is_quiet() {
+        return run_git_checked(args);
+    }
+    let mut cmd = git(args);
+    cmd.stdout(Stdio::null()).stderr(Stdio::null());
+    if dry_run(&cmd) {
+        return Ok(());
+    }
+    let status = cmd.status().map_err(|err| spawn_error(&cmd, err))?;
+    check_status(&cmd, status)
+}
+
+/// Like [`run_git_checked`], but shows a spinner on stderr while git runs.
+/// git's output is collected and printed once it's done, so the two don't
+/// draw over each other. Without a terminal or under `--quiet`, this is just
+/// [`run_git_checked`].
+///
+/// Credential and ssh prompts would be drawn over too, so they're turned off
+/// while the spinner runs. If git fails for want of one, it's run again
+/// without the spinner so the prompt can be answered.
+fn run_git_spinner(message: &str, args: &[&str]) -> Result<()> {
+    // There's no telling whether a custom ssh command can prompt
+    let custom_ssh = env::var_os("GIT_SSH_COMMAND").is_some()
+        || env::var_os("GIT_SSH").is_some()
+        || !git_query(&["config", "core.sshCommand"])?.is_empty();
+    if is_quiet() || !std::io::stderr().is_terminal() || custom_ssh {
+        return run_git_checked(args);
+    }
+    let mut cmd = git(args);
+    if dry_run(&cmd) {
+        return Ok(());
+    }
+    cmd.env("GIT_TERMINAL_PROMPT", "0")
+        .env("GIT_SSH_COMMAND", "ssh -o BatchMode=yes")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let child = cmd.spawn().map_err(|err| spawn_error(&cmd, err))?;
+
+    let done = Arc::new(AtomicBool::new(false));
+    let spinner = {
+        let done = Arc::clone(&done);
+        let message = message.to_string();
+        thread::spawn(move || {
+            let mut stderr = std::io::stderr();
+            for frame in SPINNER_FRAMES.iter().cycle() {
+                if done.load(Ordering::Relaxed) {
+                    break;
+                }
+                let _ = write!(stderr, "\r{} {}...", frame, message);
+                let _ = stderr.flush();
+                thread::sleep(Duration::from_millis(80));
+            }
+            // Clear the line for whatever comes next
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+        })
     };
-    let formatted = formatted.trim();
+    let output = child.wait_with_output();
+    done.store(true, Ordering::Relaxed);
+    let _ = spinner.join();
 
-    Command::new("git")
-        .arg("add")
-        .arg("-A")
-        .arg("*")
-        .arg(".*")
-        .spawn()?
-        .wait()?;
-    let mut cmd = Command::new("git");
+    let output = output?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() && PROMPT_FAILURES.iter().any(|e| stderr.contains(e)) {
+        return run_git_checked(args);
+    }
+    std::io::stdout().write_all(&output.stdout)?;
+    std::io::stderr().write_all(&output.stderr)?;
+    check_status(&cmd, output.status)
+}
+
+/// What git and ssh print when they needed to prompt for something but
+/// weren't allowed to.
+const PROMPT_FAILURES: [&str; 6] = [
+    "terminal prompts disabled",
+    "could not read Username",
+    "could not read Password",
+    "Host key verification failed",
+    "Permission denied",
+    "Authentication failed",
+];
 
-    cmd.arg("commit");
-    if no_verify {
-        cmd.arg("--no-verify");
+const SPINNER_FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// Like [`run_git_checked`], for output that may be piped into eg. `head`.
+fn run_git_piped(args: &[&str]) -> Result<()> {
+    let status = run_git(args)?;
+    // The reader going away, eg. `head` having seen enough, isn't a failure
+    if broken_pipe(status) {
+        return Ok(());
     }
+    check_status(&git(args), status)
+}
 
-    cmd.arg("-am")
-        .arg(formatted)
-        .spawn()?
-        .wait()?;
-    Ok(())
+/// Whether the process was killed by `SIGPIPE`.
+#[cfg(unix)]
+fn broken_pipe(status: ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal() == Some(13)
+}
+
+#[cfg(not(unix))]
+fn broken_pipe(_status: ExitStatus) -> bool {
+    false
+}
+
+/// A git command that exited unsuccessfully. [`handle`] exits with the same
+/// code, so scripts can tell failures apart like they would with git.
+#[derive(Debug)]
+struct GitError {
+    command: String,
+    status: ExitStatus,
+}
+
+impl fmt::Display for GitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "`{}` failed with {}", self.command, self.status)
+    }
 }
 
-fn log(short: bool) -> Result<()> {
+impl Error for GitError {}
+
+fn check_status(cmd: &Command, status: ExitStatus) -> Result<()> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(GitError {
+            command: command_line(cmd),
+            status,
+        }
+        .into())
+    }
+}
+
+/// Like [`run_git`], but captures stdout and stderr instead of inheriting them.
+fn run_git_output(args: &[&str]) -> Result<Output> {
+    let mut cmd = git(args);
+    if dry_run(&cmd) {
+        return Ok(Output {
+            status: ExitStatus::default(),
+            stdout: vec![],
+            stderr: vec![],
+        });
+    }
+    Ok(cmd.output().map_err(|err| spawn_error(&cmd, err))?)
+}
+
+/// Runs a read-only git command and returns its trimmed stdout. Unlike
+/// [`run_git_output`], this also runs under `--dry-run`.
+fn git_query(args: &[&str]) -> Result<String> {
+    let mut cmd = git(args);
+    let output = cmd.output().map_err(|err| spawn_error(&cmd, err))?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn git(args: &[&str]) -> Command {
     let mut cmd = Command::new("git");
-    cmd.arg("log");
-    if short {
-        cmd.arg("--oneline");
+    cmd.args(args);
+    cmd
+}
+
+fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// The args to start a git `command` whose output may be paged. The pager is
+/// skipped under `--no-pager`, and when piped so that eg. `qit log | head`
+/// streams straight through.
+fn paged(command: &str) -> Vec<&str> {
+    if NO_PAGER.load(Ordering::Relaxed) || !std::io::stdout().is_terminal() {
+        vec!["--no-pager", command]
+    } else {
+        vec![command]
+    }
+}
+
+fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Prints `cmd` if `--dry-run` is set, returning whether it should be skipped.
+fn dry_run(cmd: &Command) -> bool {
+    let dry_run = is_dry_run();
+    if dry_run {
+        println!("{}", command_line(cmd));
+    }
+    dry_run
+}
+
+fn spawn_error(cmd: &Command, err: IOError) -> IOError {
+    IOError::other(format!("Unable to run `{}`: {}", command_line(cmd), err))
+}
+
+/// Renders `cmd` as a line that can be pasted into a shell.
+fn command_line(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| shell_quote(&arg.to_string_lossy()))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:@%+,~^".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// How commit subjects are rendered.
+#[derive(Debug, Default, Clone, Copy)]
+struct SubjectFormat {
+    /// Plain Conventional Commits, `type(scope): message`, with no emoji.
+    conventional: bool,
+    /// `type: message emoji` rather than `emoji type: message`.
+    emoji_suffix: bool,
+    /// Leave the emoji out, like `QIT_DISABLE_EMOJIS` does.
+    no_emoji: bool,
+}
+
+impl SubjectFormat {
+    fn new(config: &Config) -> SubjectFormat {
+        SubjectFormat {
+            conventional: matches!(env::var("QIT_FORMAT"), Ok(value) if value == "conventional"),
+            emoji_suffix: config.emoji_position() == EmojiPosition::Suffix,
+            no_emoji: false,
+        }
     }
-    cmd.spawn()?.wait()?;
-    Ok(())
 }
 
-fn push(force: bool) -> Result<()> {
-    let pending_changes = if let Ok(count) = repo_status() {
-        count > 0
+fn format_subject(
+    config: &Config,
+    format: SubjectFormat,
+    type_: &str,
+    area: &Option<&str>,
+    message: &str,
+    breaking: bool,
+) -> Result<String> {
+    let emoji = emoji(config, type_)?;
+    let emoji = if format.conventional || format.no_emoji {
+        ""
+    } else {
+        &emoji
+    };
+    if let Some(area) = area.filter(|area| !config.is_allowed_scope(area)) {
+        let allowed = config.allowed_scopes().unwrap_or_default().join(", ");
+        return Err(IOError::other(format!(
+            "Area `{}` isn't allowed, expected one of: {}",
+            area, allowed
+        ))
+        .into());
+    }
+    let breaking = if breaking { "!" } else { "" };
+    let header = match area {
+        Some(area) => format!("{}({}){}:", type_, area, breaking),
+        None => format!("{}{}:", type_, breaking),
+    };
+    let parts = if format.emoji_suffix {
+        [&header, message.trim(), emoji]
     } else {
-        false
+        [emoji, &header, message.trim()]
     };
+    let formatted = parts
+        .iter()
+        .filter(|part| !part.is_empty())
+        .copied()
+        .collect::<Vec<&str>>()
+        .join(" ");
+    Ok(formatted)
+}
 
-    if pending_changes && !force {
-        return Err(IOError::new(ErrorKind::Other, "There are uncommitted changes").into());
+/// The emoji for the given commit type, its text tag under
+/// `QIT_EMOJI_STYLE=text`, or nothing if emojis are disabled.
+fn emoji<'a>(config: &'a Config, type_: &str) -> Result<Cow<'a, str>> {
+    let emoji = config.emoji(type_).ok_or_else(|| unknown_type(type_))?;
+    let style = match env::var("QIT_EMOJI_STYLE").as_deref() {
+        Ok("text") => EmojiStyle::Text,
+        Ok("emoji") => EmojiStyle::Emoji,
+        _ => config.emoji_style(),
+    };
+    if env_enabled("QIT_DISABLE_EMOJIS") {
+        Ok(Cow::Borrowed(""))
+    } else if style == EmojiStyle::Text {
+        Ok(Cow::Owned(config.tag(type_)))
+    } else {
+        Ok(Cow::Borrowed(emoji))
     }
-    let mut cmd = Command::new("git");
-    cmd.arg("push");
-    if force {
-        cmd.arg("--force");
+}
+
+/// Fills in the `{type}`, `{scope}` and `{message}` placeholders of the
+/// configured commit template.
+fn render_template(
+    template: &str,
+    type_: &str,
+    scope: Option<&str>,
+    message: &str,
+) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or_else(|| {
+            IOError::other(format!(
+                "Unclosed placeholder in template: {}",
+                &rest[start..]
+            ))
+        })?;
+        let value = match &rest[start + 1..start + end] {
+            "type" => type_,
+            "scope" => scope.unwrap_or(""),
+            "message" => message,
+            name => {
+                return Err(IOError::other(format!(
+                    "Unknown placeholder in template: {{{}}}, expected {{type}}, {{scope}} or {{message}}",
+                    name
+                ))
+                .into())
+            }
+        };
+        out.push_str(value);
+        rest = &rest[start + end + 1..];
     }
-    cmd.spawn()?.wait()?;
-    Ok(())
+    out.push_str(rest);
+    Ok(out)
 }
 
-fn undo() -> Result<()> {
-    Command::new("git")
-        .arg("reset")
-        .arg("--soft")
-        .arg("HEAD~1")
-        .spawn()?
-        .wait()?;
-    Ok(())
+fn unknown_type(type_: &str) -> Box<dyn Error> {
+    IOError::other(format!("Unknown commit type: {}", type_)).into()
 }
 
-fn switch_branch(branch: &str) -> Result<()> {
-    let mut cmd = Command::new("git");
-    let cmd = cmd
-        .arg("checkout")
-        .arg(branch)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null());
-    let output = cmd.spawn()?.wait()?;
-    if output.success() {
+/// The type from `QIT_DEFAULT_TYPE`, or else the config, to use when none is
+/// given.
+fn default_type(config: &Config) -> Result<Option<String>> {
+    let type_ = match env::var("QIT_DEFAULT_TYPE") {
+        Ok(type_) if !type_.is_empty() => type_,
+        _ => match config.default_type() {
+            Some(type_) => type_.to_string(),
+            None => return Ok(None),
+        },
+    };
+    if !config.is_type(&type_) {
+        return Err(IOError::other(format!("Unknown default commit type: {}", type_)).into());
+    }
+    Ok(Some(config.canonical_type(&type_).to_string()))
+}
+
+/// Whether the given boolean git config option is enabled for the current
+/// repository.
+fn git_config_enabled(key: &str) -> bool {
+    open_repo()
+        .and_then(|repo| Ok(repo.config()?.get_bool(key)?))
+        .unwrap_or(false)
+}
+
+/// Whether the given environment variable is set to `true`.
+fn env_enabled(name: &str) -> bool {
+    matches!(env::var(name), Ok(value) if value == "true")
+}
+
+/// Checks that `value` looks like `Key: value`, returning it tidied up.
+fn parse_trailer(trailer: &str) -> Result<String> {
+    match trailer.split_once(':') {
+        Some((key, value))
+            if !key.trim().is_empty()
+                && !key.trim().contains(char::is_whitespace)
+                && !value.trim().is_empty() =>
+        {
+            Ok(format!("{}: {}", key.trim(), value.trim()))
+        }
+        _ => Err(IOError::other(format!("`{}` is not of the form `Key: value`", trailer)).into()),
+    }
+}
+
+/// Checks that `value` looks like `Name <email>`.
+fn validate_identity(value: &str) -> Result<()> {
+    let valid = match value
+        .trim()
+        .strip_suffix('>')
+        .and_then(|v| v.split_once('<'))
+    {
+        Some((name, email)) => {
+            !name.trim().is_empty()
+                && !name.contains('>')
+                && email.contains('@')
+                && !email.contains(char::is_whitespace)
+                && !email.contains('<')
+        }
+        None => false,
+    };
+    if valid {
         Ok(())
     } else {
-        Command::new("git")
-            .arg("checkout")
-            .arg("-b")
-            .arg(branch)
-            .spawn()?
-            .wait()?;
-        Ok(())
+        Err(IOError::other(format!("`{}` is not of the form `Name <email>`", value)).into())
     }
 }
 
-// Helpers //
+fn parse_positive(name: &str, value: &str) -> Result<usize> {
+    match value.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(IOError::other(format!(
+            "{} must be a positive integer, got `{}`",
+            name, value
+        ))
+        .into()),
+    }
+}
 
-fn repo_status() -> Result<usize> {
-    let repo = Repository::open(".")?;
-    let modified_files = repo
-        .statuses(Some(git2::StatusOptions::new().include_untracked(true)))?
-        .iter()
-        .filter(|s| !s.status().is_ignored())
-        .count();
-    Ok(modified_files)
+fn pending_changes() -> Result<bool> {
+    Ok(repo_status()?.total > 0)
+}
+
+const GREEN: &str = "32";
+const RED: &str = "31";
+const YELLOW: &str = "33";
+
+/// Wraps `text` in the given ANSI colour, if stdout is a terminal.
+fn paint(color: &str, text: &str) -> String {
+    if std::io::stdout().is_terminal() {
+        format!("\x1b[{}m{}\x1b[0m", color, text)
+    } else {
+        text.to_string()
+    }
+}
+
+fn index_change(status: git2::Status) -> Option<&'static str> {
+    if status.is_index_new() {
+        Some("new")
+    } else if status.is_index_modified() {
+        Some("modified")
+    } else if status.is_index_deleted() {
+        Some("deleted")
+    } else if status.is_index_renamed() {
+        Some("renamed")
+    } else if status.is_index_typechange() {
+        Some("typechange")
+    } else {
+        None
+    }
+}
+
+fn worktree_change(status: git2::Status) -> Option<&'static str> {
+    if status.is_wt_modified() {
+        Some("modified")
+    } else if status.is_wt_deleted() {
+        Some("deleted")
+    } else if status.is_wt_renamed() {
+        Some("renamed")
+    } else if status.is_wt_typechange() {
+        Some("typechange")
+    } else {
+        None
+    }
+}
+
+/// The name of the checked-out branch, or `None` if HEAD is detached.
+fn current_branch(repo: &Repository) -> Option<String> {
+    let head = repo.head().ok()?;
+    if head.is_branch() {
+        head.shorthand().map(|name| name.to_string())
+    } else {
+        None
+    }
+}
+
+/// The part of the current branch name after the last `/`, e.g. `cli` for
+/// `feature/cli`.
+fn branch_scope() -> Result<Option<String>> {
+    let repo = open_repo()?;
+    let scope = current_branch(&repo)
+        .and_then(|branch| branch.rsplit_once('/').map(|(_, scope)| scope.to_string()))
+        .filter(|scope| !scope.is_empty());
+    Ok(scope)
+}
+
+/// The part of the current branch name matched by `[message]
+/// prefix_from_branch`, eg. `PROJ-42` for `feature/PROJ-42-thing`.
+fn branch_prefix(config: &Config) -> Result<Option<String>> {
+    let pattern = match config.branch_prefix() {
+        Some(pattern) => pattern,
+        None => return Ok(None),
+    };
+    let branch = match current_branch(&open_repo()?) {
+        Some(branch) => branch,
+        None => return Ok(None),
+    };
+    let prefix = pattern.captures(&branch).and_then(|captures| {
+        captures
+            .get(1)
+            .or_else(|| captures.get(0))
+            .map(|prefix| prefix.as_str().to_string())
+    });
+    Ok(prefix.filter(|prefix| !prefix.is_empty()))
+}
+
+/// The name of the current branch, if it has no upstream configured.
+fn branch_without_upstream() -> Result<Option<String>> {
+    let repo = open_repo()?;
+    let name = match current_branch(&repo) {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    let branch = repo.find_branch(&name, git2::BranchType::Local)?;
+    if branch.upstream().is_ok() {
+        Ok(None)
+    } else {
+        Ok(Some(name))
+    }
+}
+
+/// The name of the remote that `branch`'s upstream is on, if it has one.
+fn upstream_remote(repo: &Repository, branch: &str) -> Option<String> {
+    let remote = repo
+        .branch_upstream_remote(&format!("refs/heads/{}", branch))
+        .ok()?;
+    remote.as_str().map(|remote| remote.to_string())
+}
+
+/// How many commits the current branch's upstream has that it doesn't, as of
+/// the last fetch. Zero if there's no upstream, or `remote` isn't where it is.
+fn commits_behind_upstream(remote: Option<&str>) -> Result<usize> {
+    let repo = open_repo()?;
+    let name = match current_branch(&repo) {
+        Some(name) => name,
+        None => return Ok(0),
+    };
+    if remote.is_some() && remote.map(String::from) != upstream_remote(&repo, &name) {
+        return Ok(0);
+    }
+    let upstream = match repo.find_branch(&name, git2::BranchType::Local)?.upstream() {
+        Ok(upstream) => upstream.get().peel_to_commit()?.id(),
+        Err(_) => return Ok(0),
+    };
+    let head = repo.head()?.peel_to_commit()?.id();
+    let (_, behind) = repo.graph_ahead_behind(head, upstream)?;
+    Ok(behind)
+}
+
+/// Whether any of the last `count` commits are already on the current
+/// branch's upstream. Only the oldest needs checking, as the newer ones can't
+/// have been pushed without it.
+fn commits_pushed(count: usize) -> Result<bool> {
+    let repo = open_repo()?;
+    let name = match current_branch(&repo) {
+        Some(name) => name,
+        None => return Ok(false),
+    };
+    let upstream = match repo.find_branch(&name, git2::BranchType::Local)?.upstream() {
+        Ok(upstream) => upstream.get().peel_to_commit()?.id(),
+        Err(_) => return Ok(false),
+    };
+    let oldest = repo
+        .revparse_single(&format!("HEAD~{}", count - 1))?
+        .peel_to_commit()?
+        .id();
+    Ok(oldest == upstream || repo.graph_descendant_of(upstream, oldest)?)
+}
+
+/// Fails if a merge, rebase or cherry-pick stopped partway through, since
+/// committing or pushing then would only make things more confusing.
+fn check_no_operation_in_progress() -> Result<()> {
+    use git2::RepositoryState;
+    let repo = open_repo()?;
+    let (operation, command) = match repo.state() {
+        RepositoryState::Merge => ("merge", "git merge"),
+        RepositoryState::Rebase | RepositoryState::RebaseMerge => ("rebase", "qit rebase"),
+        // Committing is how an `edit` stop works, so only conflicts count
+        RepositoryState::RebaseInteractive if repo.index()?.has_conflicts() => {
+            ("rebase", "qit rebase")
+        }
+        RepositoryState::CherryPick | RepositoryState::CherryPickSequence => {
+            ("cherry-pick", "qit cherry-pick")
+        }
+        _ => return Ok(()),
+    };
+    Err(IOError::other(format!(
+        "Resolve the in-progress {} first, `qit conflicts` lists what's left. Then run `{} --continue`, or `{} --abort` to give up",
+        operation, command, command
+    ))
+    .into())
+}
+
+fn head_detached() -> Result<bool> {
+    Ok(open_repo()?.head_detached()?)
+}
+
+fn status_options() -> git2::StatusOptions {
+    let mut options = git2::StatusOptions::new();
+    options.include_untracked(true);
+    options
+}
+
+/// Opens the repository containing the current directory.
+fn open_repo() -> Result<Repository> {
+    match Repository::discover(".") {
+        Ok(repo) => Ok(repo),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => {
+            Err(IOError::other("Not inside a git repository").into())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The number of changed files in the working tree. A file with both staged
+/// and unstaged changes counts towards both, but only once in `total`.
+#[derive(Debug, Default, Serialize)]
+struct StatusCounts {
+    #[serde(rename = "modified")]
+    total: usize,
+    staged: usize,
+    unstaged: usize,
+    untracked: usize,
+    /// `None` on a detached HEAD.
+    branch: Option<String>,
+}
+
+fn repo_status() -> Result<StatusCounts> {
+    let repo = open_repo()?;
+    let mut counts = StatusCounts {
+        branch: current_branch(&repo),
+        ..StatusCounts::default()
+    };
+    for entry in repo.statuses(Some(&mut status_options()))?.iter() {
+        let status = entry.status();
+        if status.is_ignored() {
+            continue;
+        }
+        counts.total += 1;
+        if status.is_wt_new() {
+            counts.untracked += 1;
+            continue;
+        }
+        if index_change(status).is_some() {
+            counts.staged += 1;
+        }
+        if worktree_change(status).is_some() {
+            counts.unstaged += 1;
+        }
+    }
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(seconds: i64, offset_minutes: i32) -> String {
+        format_time(git2::Time::new(seconds, offset_minutes))
+    }
+
+    #[test]
+    fn format_time_epoch() {
+        assert_eq!(time(0, 0), "1970-01-01 00:00:00 +0000");
+        assert_eq!(time(1640005391, 60), "2021-12-20 14:03:11 +0100");
+    }
+
+    #[test]
+    fn format_time_negative_offsets() {
+        assert_eq!(time(1700000000, -210), "2023-11-14 18:43:20 -0330");
+        // The offset can move the date back a day
+        assert_eq!(time(0, -300), "1969-12-31 19:00:00 -0500");
+    }
+
+    #[test]
+    fn format_time_before_1970() {
+        assert_eq!(time(-1, 0), "1969-12-31 23:59:59 +0000");
+        assert_eq!(time(-31622400, -300), "1968-12-30 19:00:00 -0500");
+        assert_eq!(time(-2208988800, 0), "1900-01-01 00:00:00 +0000");
+    }
+
+    #[test]
+    fn format_time_leap_days() {
+        assert_eq!(time(951782400, 0), "2000-02-29 00:00:00 +0000");
+        assert_eq!(time(-58017600, 0), "1968-02-29 12:00:00 +0000");
+        // 1900 and 2100 aren't leap years
+        assert_eq!(time(-2203891201, 0), "1900-02-28 23:59:59 +0000");
+        assert_eq!(time(-2203891200, 0), "1900-03-01 00:00:00 +0000");
+        assert_eq!(time(4107542400, 0), "2100-03-01 00:00:00 +0000");
+        // 23:30 on a leap day is already March in +0100
+        assert_eq!(time(1709249400, 60), "2024-03-01 00:30:00 +0100");
+    }
+
+    fn subject(
+        config: &str,
+        format: SubjectFormat,
+        area: Option<&str>,
+        breaking: bool,
+    ) -> Result<String> {
+        let config = Config::parse(config)?;
+        format_subject(&config, format, "fix", &area, " Fix it ", breaking)
+    }
+
+    #[test]
+    fn format_subject_default() {
+        let format = SubjectFormat::default();
+        assert_eq!(subject("", format, None, false).unwrap(), "🐛 fix: Fix it");
+        assert_eq!(
+            subject("", format, Some("cli"), false).unwrap(),
+            "🐛 fix(cli): Fix it"
+        );
+    }
+
+    #[test]
+    fn format_subject_breaking() {
+        let format = SubjectFormat::default();
+        assert_eq!(subject("", format, None, true).unwrap(), "🐛 fix!: Fix it");
+        assert_eq!(
+            subject("", format, Some("cli"), true).unwrap(),
+            "🐛 fix(cli)!: Fix it"
+        );
+    }
+
+    #[test]
+    fn format_subject_conventional() {
+        let format = SubjectFormat {
+            conventional: true,
+            ..SubjectFormat::default()
+        };
+        assert_eq!(subject("", format, None, false).unwrap(), "fix: Fix it");
+        assert_eq!(
+            subject("", format, Some("cli"), true).unwrap(),
+            "fix(cli)!: Fix it"
+        );
+    }
+
+    #[test]
+    fn format_subject_emoji_suffix() {
+        let format = SubjectFormat {
+            emoji_suffix: true,
+            ..SubjectFormat::default()
+        };
+        assert_eq!(subject("", format, None, false).unwrap(), "fix: Fix it 🐛");
+        let config = "[format]\nemoji_position = \"suffix\"";
+        let format = SubjectFormat::new(&Config::parse(config).unwrap());
+        assert_eq!(
+            subject(config, format, None, false).unwrap(),
+            "fix: Fix it 🐛"
+        );
+    }
+
+    #[test]
+    fn format_subject_no_emoji() {
+        let format = SubjectFormat {
+            no_emoji: true,
+            ..SubjectFormat::default()
+        };
+        assert_eq!(subject("", format, None, false).unwrap(), "fix: Fix it");
+    }
+
+    #[test]
+    fn format_subject_allowed_scopes() {
+        let config = r#"allowed_scopes = ["cli"]"#;
+        let format = SubjectFormat::default();
+        assert!(subject(config, format, Some("cli"), false).is_ok());
+        assert!(subject(config, format, Some("api"), false).is_err());
+        assert!(subject(config, format, None, false).is_ok());
+        // Any area goes without the allowlist
+        assert!(subject("", format, Some("api"), false).is_ok());
+    }
+
+    #[test]
+    fn render_template_placeholders() {
+        let rendered = render_template("{type}({scope}): {message}", "fix", Some("cli"), "Fix it");
+        assert_eq!(rendered.unwrap(), "fix(cli): Fix it");
+        let rendered = render_template("Scope: {scope}.", "fix", None, "Fix it");
+        assert_eq!(rendered.unwrap(), "Scope: .");
+        assert_eq!(render_template("plain", "fix", None, "").unwrap(), "plain");
+    }
+
+    #[test]
+    fn render_template_errors() {
+        assert!(render_template("{nope}", "fix", None, "").is_err());
+        assert!(render_template("{type", "fix", None, "").is_err());
+    }
+
+    #[test]
+    fn parse_trailer_tidies() {
+        assert_eq!(
+            parse_trailer(" Reviewed-by :  Bob ").unwrap(),
+            "Reviewed-by: Bob"
+        );
+        assert_eq!(parse_trailer("Refs: a:b").unwrap(), "Refs: a:b");
+    }
+
+    #[test]
+    fn parse_trailer_rejects() {
+        for trailer in ["Reviewed by: Bob", "Key:", ": value", "no colon"] {
+            assert!(parse_trailer(trailer).is_err(), "{}", trailer);
+        }
+    }
+
+    #[test]
+    fn validate_identity_accepts() {
+        assert!(validate_identity("Jane Doe <jane@example.com>").is_ok());
+        assert!(validate_identity(" Jane <jane@example.com> ").is_ok());
+    }
+
+    #[test]
+    fn validate_identity_rejects() {
+        for identity in [
+            "<jane@example.com>",
+            "Jane jane@example.com",
+            "Jane <jane>",
+            "Jane <jane @example.com>",
+            "Jane <<jane@example.com>",
+            "Jane> <jane@example.com>",
+        ] {
+            assert!(validate_identity(identity).is_err(), "{}", identity);
+        }
+    }
+
+    #[test]
+    fn shell_quote_args() {
+        assert_eq!(shell_quote("--author=a@b.c"), "--author=a@b.c");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("Fix it"), "'Fix it'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
 }